@@ -0,0 +1,89 @@
+//! Interpreter speed on small loop-heavy programs, where most of the time goes
+//! to variable lookups and function calls, along with the bytecode VM's speed on
+//! the ones it can compile. Run with `cargo bench --bench interp`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+
+const LOOP: &str = "
+begin {
+	var i = 0;
+	var sum = 0;
+	while i < 1000000 {
+		sum += i % 7;
+		i += 1;
+	}
+}
+";
+
+const CALLS: &str = "
+func add(a, b) {
+	return a + b;
+}
+
+begin {
+	var sum = 0;
+	for (var i = 0; i < 200000; i += 1) {
+		sum = add(sum, i);
+	}
+}
+";
+
+/// Locals declared in nested scopes and read from inside them, so lookups
+/// have to get past inner scopes to reach outer variables
+const SCOPES: &str = "
+begin {
+	var total = 0;
+	var step = 3;
+	for (var i = 0; i < 300000; i += 1) {
+		var x = i * step;
+		if x % 2 == 0 {
+			var y = x + total % 5;
+			total += y % 11;
+		}
+	}
+}
+";
+
+/// Repeatedly read elements of a 10k element array
+fn array_reads() -> String {
+    let elements = vec!["1"; 10_000].join(", ");
+    format!("
+begin {{
+	var a = [{elements}];
+	var sum = 0;
+	for (var i = 0; i < 20000; i += 1) {{
+		sum += a[i % 10000];
+	}}
+}}
+")
+}
+
+fn interp(c: &mut Criterion) {
+    let array_reads = array_reads();
+    for (name, src) in [("loop", LOOP), ("calls", CALLS), ("scopes", SCOPES), ("array reads", &array_reads)] {
+        let mut group = c.benchmark_group(name);
+        group.sample_size(10);
+        group.bench_function("interp", |b| {
+            b.iter(|| black_box(foolang::run_source(black_box(src)).unwrap()))
+        });
+
+        // the same program compiled to bytecode, if the compiler supports everything in it
+        let items = || {
+            let tokens = foolang::lex::lex_tokens(src).collect::<Result<Vec<_>, _>>().unwrap();
+            foolang::ast::parse_items(tokens.into_iter()).collect::<Result<Vec<_>, _>>().unwrap()
+        };
+        if foolang::compile::compile(items().into_iter()).is_ok() {
+            group.bench_function("vm", |b| {
+                b.iter(|| {
+                    let bytecode = foolang::compile::compile(black_box(items()).into_iter()).unwrap();
+                    black_box(foolang::vm::Vm::new(&bytecode).run().unwrap());
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, interp);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+//! Lexing and parsing throughput on a big generated program. Run with
+//! `cargo bench --bench lex_parse`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use foolang::{ast, lex};
+use std::hint::black_box;
+
+const STATEMENTS: usize = 10_000;
+
+fn lex_parse(c: &mut Criterion) {
+    let src = foolang::generate_source(STATEMENTS);
+    let tokens = lex::lex_tokens(&src)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    let mut group = c.benchmark_group(format!("{STATEMENTS} statements"));
+    group.throughput(Throughput::Elements(tokens.len() as u64));
+    group.bench_function("lex_tokens", |b| {
+        b.iter(|| {
+            for token in lex::lex_tokens(black_box(&src)) {
+                black_box(token.unwrap());
+            }
+        })
+    });
+    group.bench_function("parse_items", |b| {
+        b.iter(|| {
+            for item in ast::parse_items(black_box(tokens.clone()).into_iter()) {
+                black_box(item.unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lex_parse);
+criterion_main!(benches);
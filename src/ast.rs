@@ -1,219 +1,1794 @@
-use crate::lex::Token;
+use crate::lex::{self, Span, Token};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem::discriminant;
+use std::rc::Rc;
 
-pub fn parse_items<'a>(tokens: impl Iterator<Item=Token<'a>>) -> impl Iterator<Item=Item<'a>> {
-    ItemStream { tokens: tokens.peekable(), }
+/// How deeply expressions and blocks can nest by default before parsing gives up
+/// with `ParseError::NestingTooDeep`. Everything that walks the AST recurses, so
+/// this keeps pathological input from overflowing the stack. A level can take
+/// over 20 KiB of stack in a debug build, so this is low enough for a full run to
+/// fit in the 2 MiB stack that spawned threads get by default.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// How many binary operators can follow each other before parsing gives up with
+/// `ParseError::ChainTooLong`. Chains like `1 + 1 + ... + 1` nest down their lhs,
+/// and dropping one recurses once per operator, at up to 100 bytes of stack each.
+pub const MAX_CHAIN_LENGTH: usize = 1000;
+
+pub fn parse_items<'a>(tokens: impl Iterator<Item=(Token<'a>, Span)>) -> impl Iterator<Item=Result<Item<'a>, ParseError<'a>>> {
+    parse_items_with_max_depth(tokens, DEFAULT_MAX_DEPTH)
+}
+
+/// Like `parse_items`, but with a nesting limit other than `DEFAULT_MAX_DEPTH`
+pub fn parse_items_with_max_depth<'a>(
+    tokens: impl Iterator<Item=(Token<'a>, Span)>,
+    max_depth: usize,
+) -> impl Iterator<Item=Result<Item<'a>, ParseError<'a>>> {
+    ItemStream::new(tokens, Interner::default(), 0, max_depth)
+}
+
+/// Like `parse_items`, but instead of stopping at the first error, skip past it
+/// and keep going. An error in a statement skips to the end of that statement,
+/// and an error elsewhere in an item skips the rest of the item. Returns every item
+/// that parsed, even ones missing the statements that didn't, and every error.
+pub fn parse_items_recovering<'a>(
+    tokens: impl Iterator<Item=(Token<'a>, Span)>,
+) -> (Vec<Item<'a>>, Vec<ParseError<'a>>) {
+    let mut stream = ItemStream::new(tokens, Interner::default(), 0, DEFAULT_MAX_DEPTH);
+    stream.errors = Some(Vec::new());
+    let mut items = Vec::new();
+    while let Some(item) = stream.next() {
+        match item {
+            Ok(item) => items.push(item),
+            Err(e) => {
+                if let Err(e) = stream.recover(e, false) {
+                    stream.errors.as_mut().unwrap().push(e);
+                    break;
+                }
+            },
+        }
+    }
+    (items, stream.errors.unwrap())
+}
+
+/// A line of REPL input: statements, optionally followed by an expression
+/// without a semicolon whose value gets printed
+#[derive(Debug)]
+pub struct ReplInput<'a> {
+    pub body: Vec<Statement<'a>>,
+    pub value: Option<Expr<'a>>,
+}
+
+/// Parse a line of REPL input. Every line of a session has to be parsed with
+/// the same `interner`, so that a name means the same variable in all of them.
+pub fn parse_repl_input<'a>(
+    tokens: impl Iterator<Item=(Token<'a>, Span)>,
+    interner: &mut Interner,
+) -> Result<ReplInput<'a>, ParseError<'a>> {
+    let mut stream = ItemStream::new(tokens, std::mem::take(interner), 0, DEFAULT_MAX_DEPTH);
+    let result = stream.parse_stmts_with_value(None);
+    *interner = std::mem::take(&mut stream.interner);
+    let (body, value) = result?;
+    if let Some((tkn, span)) = stream.tokens.next() {
+        return Err(ParseError::UnexpectedToken(tkn, span));
+    }
+
+    Ok(ReplInput { body, value })
+}
+
+/// The interned id of a name. Within one parse (or one `Interner`), every
+/// occurrence of a name gets the same symbol, and different names different ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(pub u32);
+
+/// A variable or function name as written in the source, along with its `Symbol`.
+/// Equality and hashing only look at the symbol, which makes them cheap.
+#[derive(Debug, Clone)]
+pub struct Ident<'a> {
+    name: Name<'a>,
+    pub symbol: Symbol,
+}
+
+/// The text of an `Ident`, which is only owned once the AST has to outlive the source
+#[derive(Debug, Clone)]
+enum Name<'a> {
+    Borrowed(&'a str),
+    Owned(Rc<str>),
+}
+
+impl PartialEq for Ident<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.symbol == other.symbol
+    }
+}
+
+impl Eq for Ident<'_> {}
+
+impl Hash for Ident<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.symbol.hash(state);
+    }
+}
+
+impl Ident<'_> {
+    pub fn name(&self) -> &str {
+        match &self.name {
+            Name::Borrowed(name) => name,
+            Name::Owned(name) => name,
+        }
+    }
+
+    /// The same ident, with its name no longer borrowed from the source
+    pub fn to_owned(&self) -> Ident<'static> {
+        let name = match &self.name {
+            Name::Borrowed(name) => Rc::from(*name),
+            Name::Owned(name) => Rc::clone(name),
+        };
+        Ident { name: Name::Owned(name), symbol: self.symbol }
+    }
+}
+
+/// An ident is written as just its name
+#[cfg(feature = "serde")]
+impl serde::Serialize for Ident<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// Names are interned with `json::DESERIALIZE_INTERNER`, so that every ident
+/// loaded on a thread with the same name has the same symbol, like a parse gives.
+/// A name has to be one the lexer would give as an identifier.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Ident<'_> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        // anything else couldn't have been parsed as a name, like a keyword
+        let tokens: Vec<_> = lex::lex_tokens(&name).collect();
+        if !matches!(tokens[..], [Ok((Token::Ident(ident), _))] if ident == name) {
+            return Err(serde::de::Error::custom(format!("{name:?} isn't a valid name")));
+        }
+        Ok(crate::json::DESERIALIZE_INTERNER.with_borrow_mut(|interner| interner.intern_owned(&name)))
+    }
+}
+
+/// Hands out a `Symbol` for each distinct name. An interner keeps a copy of
+/// every name it has seen, so it can outlive the source the names came from.
+#[derive(Debug, Default)]
+pub struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+}
+
+impl Interner {
+    fn symbol(&mut self, name: &str) -> (Rc<str>, Symbol) {
+        if let Some((name, symbol)) = self.symbols.get_key_value(name) {
+            return (Rc::clone(name), *symbol);
+        }
+        let symbol = Symbol(self.symbols.len() as u32);
+        let name: Rc<str> = name.into();
+        self.symbols.insert(Rc::clone(&name), symbol);
+        (name, symbol)
+    }
+
+    pub fn intern<'a>(&mut self, name: &'a str) -> Ident<'a> {
+        let (_, symbol) = self.symbol(name);
+        Ident { name: Name::Borrowed(name), symbol }
+    }
+
+    /// Like `intern`, but the ident owns its name, sharing it with every other
+    /// ident the interner hands out for the same name this way
+    pub fn intern_owned(&mut self, name: &str) -> Ident<'static> {
+        let (name, symbol) = self.symbol(name);
+        Ident { name: Name::Owned(name), symbol }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError<'a> {
+    /// Found a token that can't appear here
+    UnexpectedToken(Token<'a>, Span),
+    /// Ran out of tokens in the middle of an item
+    UnexpectedEof,
+    /// A specific token was required, but `found` was there instead
+    ExpectedToken {
+        expected: Token<'static>,
+        found: Token<'a>,
+        span: Span,
+    },
+    /// An identifier was required, but `found` was there instead
+    ExpectedIdent(Token<'a>, Span),
+    /// A comparison operator directly followed another, as in `a < b < c`.
+    /// Comparisons don't chain; use parentheses to compare a comparison's result.
+    ChainedComparison(Token<'a>, Span),
+    /// A keyword was used where a name was required, like `var if = 1;`
+    ReservedKeyword(&'static str, Span),
+    /// A type annotation named a type that doesn't exist
+    UnknownType(&'a str, Span),
+    /// An integer literal doesn't fit in an int
+    IntegerOutOfRange(&'a str, Span),
+    /// Expressions or blocks are nested deeper than the parser's limit.
+    /// The span is of the token that went past it.
+    NestingTooDeep(Span),
+    /// More than `MAX_CHAIN_LENGTH` binary operators followed each other.
+    /// The span is of the operator that went past it.
+    ChainTooLong(Span),
+}
+
+impl<'a> ParseError<'a> {
+    /// The token the error is at, if it's about one
+    fn found_token(&self) -> Option<&Token<'a>> {
+        match self {
+            Self::UnexpectedToken(tkn, _)
+                | Self::ExpectedToken { found: tkn, .. }
+                | Self::ExpectedIdent(tkn, _)
+                | Self::ChainedComparison(tkn, _) => Some(tkn),
+            _ => None,
+        }
+    }
+
+    /// Where in the source the error is, if anywhere
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::UnexpectedToken(_, span)
+                | Self::ExpectedToken { span, .. }
+                | Self::ExpectedIdent(_, span)
+                | Self::ChainedComparison(_, span)
+                | Self::ReservedKeyword(_, span)
+                | Self::UnknownType(_, span)
+                | Self::IntegerOutOfRange(_, span)
+                | Self::NestingTooDeep(span)
+                | Self::ChainTooLong(span) => Some(*span),
+            Self::UnexpectedEof => None,
+        }
+    }
 }
 
-struct ItemStream<'a, T: Iterator<Item=Token<'a>>> {
+impl std::fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedToken(tkn, span) => write!(f, "unexpected token `{tkn}` at byte {}", span.start),
+            Self::UnexpectedEof => write!(f, "unexpected end of input"),
+            Self::ExpectedToken { expected, found, span } => {
+                write!(f, "expected `{expected}`, found `{found}` at byte {}", span.start)
+            },
+            Self::ExpectedIdent(found, span) => write!(f, "expected identifier, found `{found}` at byte {}", span.start),
+            Self::ChainedComparison(tkn, span) => {
+                write!(f, "chained comparison `{tkn}` at byte {} needs parentheses", span.start)
+            },
+            Self::ReservedKeyword(kw, span) => {
+                write!(f, "{kw} is a reserved keyword and can't be used as a name at byte {}", span.start)
+            },
+            Self::UnknownType(name, span) => write!(f, "unknown type {name} at byte {}", span.start),
+            Self::IntegerOutOfRange(text, span) => write!(f, "integer literal {text} is out of range at byte {}", span.start),
+            Self::NestingTooDeep(span) => write!(f, "nesting is too deep at byte {}", span.start),
+            Self::ChainTooLong(span) => {
+                write!(f, "more than {MAX_CHAIN_LENGTH} operators in a row at byte {}", span.start)
+            },
+        }
+    }
+}
+
+struct ItemStream<'a, T: Iterator<Item=(Token<'a>, Span)>> {
     tokens: std::iter::Peekable<T>,
+    interner: Interner,
+    /// How many `nested` calls are in progress
+    depth: usize,
+    max_depth: usize,
+    /// The errors skipped past so far, when parsing with `parse_items_recovering`.
+    /// `None` stops at the first error instead.
+    errors: Option<Vec<ParseError<'a>>>,
 }
 
-impl<'a, T: Iterator<Item=Token<'a>>> ItemStream<'a, T> {
-    /// Parse the parenthesized args of a function call
-    fn parse_call(&mut self) -> Vec<Expr<'a>> {
-        assert!(matches!(self.tokens.next(), Some(Token::LeftParen)));
+impl<'a, T: Iterator<Item=(Token<'a>, Span)>> ItemStream<'a, T> {
+    fn new(tokens: T, interner: Interner, depth: usize, max_depth: usize) -> Self {
+        ItemStream { tokens: tokens.peekable(), interner, depth, max_depth, errors: None }
+    }
 
-        if let Some(Token::RightParen) = self.tokens.peek() {
-            self.tokens.next();
-            // empty params list
-            Vec::new()
+    /// Record `error` and skip past it, if recovering from errors. Otherwise, or if
+    /// there's nothing left to skip to, give it back to fail with. Returns whether
+    /// the error was at a `}`, which has already been eaten, and which in a block
+    /// is most likely the one that closes it.
+    fn recover(&mut self, error: ParseError<'a>, in_block: bool) -> Result<bool, ParseError<'a>> {
+        let Some(errors) = &mut self.errors else {
+            return Err(error);
+        };
+        if matches!(error, ParseError::UnexpectedEof) {
+            return Err(error);
+        }
+        // the token an error is at has always been eaten, so if it's a
+        // boundary, there's nothing more to skip
+        let at = error.found_token().map(discriminant);
+        errors.push(error);
+        if at == Some(discriminant(&Token::RightBrace)) {
+            return Ok(true);
+        }
+        if at != Some(discriminant(&Token::Semicolon)) {
+            self.skip_past_error(in_block);
+        }
+        Ok(false)
+    }
+
+    /// Skip tokens after an error to where parsing can pick up again: past the
+    /// next `;` or balanced `{}` block. In a block, a `}` that closes it is left
+    /// for the block to end on.
+    fn skip_past_error(&mut self, in_block: bool) {
+        let mut depth = 0;
+        while let Some(tkn) = self.peek() {
+            if in_block && depth == 0 && *tkn == Token::RightBrace {
+                return;
+            }
+            match self.tokens.next().unwrap().0 {
+                Token::LeftBrace | Token::HashLeftBrace => depth += 1,
+                Token::RightBrace if depth <= 1 => return,
+                Token::RightBrace => depth -= 1,
+                Token::Semicolon if depth == 0 => return,
+                _ => {},
+            }
+        }
+    }
+
+    /// Run `parse` one nesting level deeper, erroring at `span` if that's past the limit
+    fn nested<R>(
+        &mut self,
+        span: Span,
+        parse: impl FnOnce(&mut Self) -> Result<R, ParseError<'a>>,
+    ) -> Result<R, ParseError<'a>> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::NestingTooDeep(span));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn peek(&mut self) -> Option<&Token<'a>> {
+        self.tokens.peek().map(|(tkn, _)| tkn)
+    }
+
+    fn next_token(&mut self) -> Result<(Token<'a>, Span), ParseError<'a>> {
+        self.tokens.next().ok_or(ParseError::UnexpectedEof)
+    }
+
+    /// Consume the next token, erroring if it isn't the same kind of token as `expected`.
+    /// Returns the token's span.
+    fn expect(&mut self, expected: Token<'static>) -> Result<Span, ParseError<'a>> {
+        let (found, span) = self.next_token()?;
+        if discriminant(&found) == discriminant(&expected) {
+            Ok(span)
         } else {
-            let mut args = Vec::new();
-            loop {
-                args.push(self.parse_expr());
-                match self.tokens.next() {
-                    Some(Token::Comma) => {},
-                    Some(Token::RightParen) => break,
-                    other => panic!("Expected ',' or ')' but found: {other:#?}"),
-                }
+            Err(ParseError::ExpectedToken { expected, found, span })
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<(&'a str, Span), ParseError<'a>> {
+        match self.next_token()? {
+            (Token::Ident(ident), span) => Ok((ident, span)),
+            (other, span) => Err(ident_error(other, span)),
+        }
+    }
+
+    /// Like `expect_ident`, but interning the identifier as a variable or function name
+    fn expect_name(&mut self) -> Result<(Ident<'a>, Span), ParseError<'a>> {
+        let (name, span) = self.expect_ident()?;
+        Ok((self.interner.intern(name), span))
+    }
+
+    /// Parse the parenthesized args of a function call
+    fn parse_call(&mut self) -> Result<Vec<Expr<'a>>, ParseError<'a>> {
+        self.expect(Token::LeftParen)?;
+        let args = self.parse_comma_list(Token::RightParen, Self::parse_expr)?;
+        self.expect(Token::RightParen)?;
+        Ok(args)
+    }
+
+    /// Parse comma-separated items up until `end`, not consuming `end`.
+    /// A trailing comma is allowed after the last item, but not on its own.
+    fn parse_comma_list<I>(
+        &mut self,
+        end: Token<'static>,
+        mut parse_item: impl FnMut(&mut Self) -> Result<I, ParseError<'a>>,
+    ) -> Result<Vec<I>, ParseError<'a>> {
+        let mut items = Vec::new();
+        while self.peek() != Some(&end) {
+            items.push(parse_item(self)?);
+            if self.tokens.next_if(|(tkn, _)| *tkn == Token::Comma).is_none() {
+                break;
             }
-            args
         }
+        Ok(items)
     }
 
-    fn parse_expr(&mut self) -> Expr<'a> {
-        let lexpr = match self.tokens.next().unwrap() {
-            Token::Ident(ident) => {
-                if matches!(self.tokens.peek(), Some(Token::LeftParen)) {
-                    Expr::FuncCall {
-                        func_name: ident,
-                        args: self.parse_call(),
-                    }
-                } else {
-                    Expr::VarRef { variable: ident }
-                }
+    fn parse_expr(&mut self) -> Result<Expr<'a>, ParseError<'a>> {
+        let expr = self.parse_expr_bp(0)?;
+        self.maybe_parse_assignment(expr)
+    }
+
+    /// If the next token is `=` or a compound assignment operator like `+=`,
+    /// parse the rest of an assignment to `target`. Otherwise `target` is given back.
+    fn maybe_parse_assignment(&mut self, target: Expr<'a>) -> Result<Expr<'a>, ParseError<'a>> {
+        let op = match self.peek() {
+            Some(Token::Equals) => None,
+            tkn => match tkn.and_then(compound_assign_op) {
+                Some(op) => Some(op),
+                None => return Ok(target),
             },
-            Token::Integer(i) => Expr::IntLit { value: i.parse().unwrap() },
-            _ => panic!(),
         };
+        let (tkn, span) = self.next_token()?;
+        if !is_assignable(&target) {
+            return Err(ParseError::UnexpectedToken(tkn, span));
+        }
+        // assignment binds loosest of all, and groups to the right: `a = b = 3` is `a = (b = 3)`
+        let value = self.nested(span, Self::parse_expr)?;
+        Ok(Expr::Assign { target: Box::new(target), op, value: Box::new(value) })
+    }
 
-        match self.tokens.peek() {
-            Some(Token::Plus) => {
-                // eat the Token::Plus:
-                self.tokens.next();
-                Expr::Add { lhs: Box::new(lexpr), rhs: Box::new(self.parse_expr()) }
-            },
-            _ => lexpr,
+    /// Parse an expression by precedence climbing, only consuming binary
+    /// operators that bind at least as tightly as `min_bp`
+    fn parse_expr_bp(&mut self, min_bp: u8) -> Result<Expr<'a>, ParseError<'a>> {
+        let lhs = self.parse_primary()?;
+        self.parse_binops(lhs, min_bp)
+    }
+
+    /// Parse the rest of an expression whose leftmost operand is `lhs`
+    fn parse_binops(&mut self, mut lhs: Expr<'a>, min_bp: u8) -> Result<Expr<'a>, ParseError<'a>> {
+        // a parenthesized chain on the left continues down the same lhs
+        let mut length = 0;
+        let mut leftmost = &lhs;
+        while let Some((inner, _, _)) = split_binop(leftmost) {
+            length += 1;
+            leftmost = inner;
+        }
+        loop {
+            let bp = match self.peek().and_then(binop_power) {
+                Some(bp) if bp >= min_bp => bp,
+                _ => break,
+            };
+            let (op, op_span) = self.tokens.next().unwrap();
+            length += 1;
+            if length > MAX_CHAIN_LENGTH {
+                return Err(ParseError::ChainTooLong(op_span));
+            }
+            let rhs_bp = if is_right_associative(&op) { bp } else { bp + 1 };
+            let rhs = self.nested(op_span, |stream| stream.parse_expr_bp(rhs_bp))?;
+            lhs = make_binop(op, lhs, rhs);
+
+            if bp == COMPARISON_POWER {
+                let chained = self.tokens.next_if(|(tkn, _)| binop_power(tkn) == Some(COMPARISON_POWER));
+                if let Some((tkn, span)) = chained {
+                    return Err(ParseError::ChainedComparison(tkn, span));
+                }
+            }
         }
+
+        Ok(lhs)
     }
 
-    fn maybe_parse_statement(&mut self) -> Option<Statement<'a>> {
-        let stmt = match self.tokens.peek()? {
-            Token::Var => {
-                // eat Token::Var:
-                self.tokens.next();
+    fn parse_primary(&mut self) -> Result<Expr<'a>, ParseError<'a>> {
+        let (tkn, span) = self.next_token()?;
+        self.nested(span, |stream| {
+            let expr = match tkn {
+                Token::Ident(name) => Expr::VarRef {
+                    variable: stream.interner.intern(name),
+                    span,
+                    slot: Slot::Unresolved,
+                },
+                Token::Integer(i) => Expr::IntLit { value: parse_int(i, false, span)? },
+                Token::Float(f) => Expr::FloatLit { value: f.replace('_', "").parse().unwrap() },
+                Token::StringLit(raw) => stream.parse_string_lit(raw, span)?,
+                Token::True => Expr::BoolLit { value: true },
+                Token::False => Expr::BoolLit { value: false },
+                // unary minus binds looser than `**`, so `-2 ** 2` is `-(2 ** 2)`,
+                // and otherwise tighter than any binary operator
+                Token::Minus => match stream.tokens.next_if(|(tkn, _)| matches!(tkn, Token::Integer(_))) {
+                    // fold the sign into int literals, so that the most negative int can be written
+                    Some((Token::Integer(i), int_span)) if stream.peek() != Some(&Token::StarStar) => {
+                        Expr::IntLit { value: parse_int(i, true, int_span)? }
+                    },
+                    Some((Token::Integer(i), int_span)) => {
+                        let base = Expr::IntLit { value: parse_int(i, false, int_span)? };
+                        Expr::Neg { operand: Box::new(stream.parse_binops(base, POW_POWER)?) }
+                    },
+                    _ => Expr::Neg { operand: Box::new(stream.parse_expr_bp(POW_POWER)?) },
+                },
+                Token::Bang => Expr::Not { operand: Box::new(stream.parse_primary()?) },
+                Token::LeftParen => {
+                    let inner = stream.parse_expr()?;
+                    stream.expect(Token::RightParen)?;
+                    inner
+                },
+                Token::LeftBrace => stream.parse_block_expr()?,
+                Token::LeftBracket => Expr::ArrayLit { elements: stream.parse_array_lit()? },
+                Token::HashLeftBrace => Expr::MapLit { entries: stream.parse_map_lit()? },
+                Token::Func => Expr::Lambda(stream.parse_lambda()?),
+                Token::If => {
+                    let condition = stream.parse_expr()?;
+                    stream.parse_if_expr(condition)?
+                },
+                other => return Err(ParseError::UnexpectedToken(other, span)),
+            };
 
-                let varname = match self.tokens.next() {
-                    Some(Token::Ident(ident)) => ident,
-                   _ => panic!(),
-                };
+            stream.parse_postfix(expr, span)
+        })
+    }
+
+    /// Parse the rest of `if condition then a else b`, after its condition
+    fn parse_if_expr(&mut self, condition: Expr<'a>) -> Result<Expr<'a>, ParseError<'a>> {
+        self.expect(Token::Then)?;
+        let then_expr = self.parse_expr()?;
+        self.expect(Token::Else)?;
+        Ok(Expr::IfExpr {
+            condition: Box::new(condition),
+            then_expr: Box::new(then_expr),
+            else_expr: Box::new(self.parse_expr()?),
+        })
+    }
+
+    /// Parse the signature and body of a lambda, or of a function defined in
+    /// a block, after its already-consumed `func` and name
+    fn parse_lambda(&mut self) -> Result<Lambda<'a>, ParseError<'a>> {
+        let Signature { arg_names, arg_types, return_type } = self.parse_signature()?;
+        Ok(Lambda { arg_names, arg_types, return_type, body: self.parse_block_as_stmt_list()? })
+    }
+
+    /// Parse `func name(args) { body }` in a block, after its already-consumed `func`
+    fn parse_nested_func(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        let (name, name_span) = self.expect_name()?;
+        Ok(Statement::FuncDef {
+            name,
+            name_span,
+            func: self.parse_lambda()?,
+            slot: Slot::Unresolved,
+            defined_on_entry: false,
+        })
+    }
+
+    /// Parse any indexing operations and calls applied to `expr`, whose first token is at `span`
+    fn parse_postfix(&mut self, expr: Expr<'a>, span: Span) -> Result<Expr<'a>, ParseError<'a>> {
+        let op_span = match self.tokens.peek() {
+            Some((Token::LeftBracket | Token::LeftParen, op_span)) => *op_span,
+            _ => return Ok(expr),
+        };
+        let expr = if self.tokens.next_if(|(tkn, _)| *tkn == Token::LeftBracket).is_some() {
+            let index = self.parse_expr()?;
+            self.expect(Token::RightBracket)?;
+            Expr::Index { array: Box::new(expr), index: Box::new(index) }
+        } else {
+            Expr::FuncCall { callee: Box::new(expr), args: self.parse_call()?, span }
+        };
+        // each operation wraps the ones before it, so a long chain like `f()()()...` nests as deeply as parens do
+        self.nested(op_span, |stream| stream.parse_postfix(expr, span))
+    }
+
+    /// Parse the elements of an array literal, after its already-consumed opening bracket
+    fn parse_array_lit(&mut self) -> Result<Vec<Expr<'a>>, ParseError<'a>> {
+        let elements = self.parse_comma_list(Token::RightBracket, Self::parse_expr)?;
+        self.expect(Token::RightBracket)?;
+        Ok(elements)
+    }
+
+    /// Parse the `key: value` entries of a map literal, after its already-consumed `#{`
+    fn parse_map_lit(&mut self) -> Result<Vec<(Expr<'a>, Expr<'a>)>, ParseError<'a>> {
+        let entries = self.parse_comma_list(Token::RightBrace, |stream| {
+            let key = stream.parse_expr()?;
+            stream.expect(Token::Colon)?;
+            Ok((key, stream.parse_expr()?))
+        })?;
+        self.expect(Token::RightBrace)?;
+        Ok(entries)
+    }
+
+    /// Parse a block expression, after its already-consumed opening brace
+    fn parse_block_expr(&mut self) -> Result<Expr<'a>, ParseError<'a>> {
+        let (body, value) = self.parse_stmts_with_value(Some(&Token::RightBrace))?;
+        self.expect(Token::RightBrace)?;
+
+        Ok(Expr::Block { body, value: value.map(Box::new) })
+    }
+
+    /// Parse statements up until `end` (or the end of input if `end` is `None`),
+    /// where the last one can be an expression without a semicolon that gives a value
+    fn parse_stmts_with_value(
+        &mut self,
+        end: Option<&Token>,
+    ) -> Result<(Vec<Statement<'a>>, Option<Expr<'a>>), ParseError<'a>> {
+        let mut body = Vec::new();
 
-                assert!(matches!(self.tokens.next(), Some(Token::Equals)));
+        loop {
+            self.skip_empty_statements();
+            if let Some(Token::If) = self.peek() {
+                // `if` starts a statement, unless `then` after the condition
+                // makes it an if-expression that gives the block's value
+                let (_, span) = self.next_token()?;
+                let condition = self.parse_expr()?;
+                if let Some(Token::Then) = self.peek() {
+                    let value = self.nested(span, |stream| stream.parse_if_expr(condition))?;
+                    return Ok((body, Some(value)));
+                }
+                body.push(self.parse_if_blocks(condition)?);
+                continue;
+            }
+            if let Some(Token::While | Token::For) = self.peek() {
+                body.extend(self.maybe_parse_statement()?);
+                continue;
+            }
+            if let Some(Token::Func) = self.peek() {
+                // `func name` defines a function, and anything else is a lambda
+                // that gives the block's value
+                let (_, span) = self.next_token()?;
+                if let Some(Token::Ident(_)) = self.peek() {
+                    body.push(self.parse_nested_func()?);
+                    continue;
+                }
+                let lambda = Expr::Lambda(self.parse_lambda()?);
+                let value = self.parse_postfix(lambda, span)?;
+                return Ok((body, Some(self.parse_binops(value, 0)?)));
+            }
 
-                Some(Statement::VarDeclaration {
-                    variable: varname,
-                    value: self.parse_expr(),
-                })
+            match self.maybe_parse_simple_statement()? {
+                Some(Statement::ExprStmt { expr }) if self.peek() == end => return Ok((body, Some(expr))),
+                Some(stmt) => {
+                    self.expect(Token::Semicolon)?;
+                    body.push(stmt);
+                },
+                None if self.peek() == end => return Ok((body, None)),
+                None => return Ok((body, Some(self.parse_expr()?))),
+            }
+        }
+    }
+
+    /// Eat any bare semicolons. Empty statements do nothing, so they don't make it into the AST.
+    fn skip_empty_statements(&mut self) {
+        while self.peek() == Some(&Token::Semicolon) {
+            self.tokens.next();
+        }
+    }
+
+    fn maybe_parse_statement(&mut self) -> Result<Option<Statement<'a>>, ParseError<'a>> {
+        self.skip_empty_statements();
+        match self.peek() {
+            // block statements don't end in a semicolon:
+            Some(Token::If) => self.parse_if().map(Some),
+            Some(Token::While) => {
+                // eat Token::While:
+                self.tokens.next();
+                Ok(Some(Statement::While {
+                    condition: self.parse_expr()?,
+                    body: self.parse_block_as_stmt_list()?,
+                }))
             },
-            Token::Ident(_) => {
-                // eat Token::Ident:
-                let ident = match self.tokens.next() {
-                    Some(Token::Ident(i)) => i,
-                    _ => panic!(),
-                };
+            Some(Token::For) => self.parse_for().map(Some),
+            Some(Token::Func) => {
+                self.tokens.next();
+                self.parse_nested_func().map(Some)
+            },
+            _ => {
+                let stmt = self.maybe_parse_simple_statement()?;
+                if stmt.is_some() {
+                    self.expect(Token::Semicolon)?;
+                }
+                Ok(stmt)
+            },
+        }
+    }
 
-                assert!(matches!(self.tokens.next(), Some(Token::Equals)));
+    /// Parse a statement that isn't a block statement, without its trailing semicolon
+    fn maybe_parse_simple_statement(&mut self) -> Result<Option<Statement<'a>>, ParseError<'a>> {
+        let stmt = match self.peek() {
+            Some(Token::Var | Token::Const) => {
+                let (kw, _) = self.next_token()?;
+                let is_const = matches!(kw, Token::Const);
 
-                Some(Statement::Assignment { variable: ident, value: self.parse_expr(), })
+                let mut declarations = Vec::new();
+                loop {
+                    let (varname, span) = self.expect_name()?;
+                    self.expect(Token::Equals)?;
+                    declarations.push(Statement::VarDeclaration {
+                        variable: varname,
+                        span,
+                        value: self.parse_expr()?,
+                        is_const,
+                        slot: Slot::Unresolved,
+                    });
+                    if self.tokens.next_if(|(tkn, _)| *tkn == Token::Comma).is_none() {
+                        break;
+                    }
+                }
+
+                if declarations.len() == 1 {
+                    declarations.pop().unwrap()
+                } else {
+                    Statement::MultiVarDeclaration { declarations }
+                }
             },
-            Token::Return => {
+            // an expression whose result gets discarded, like a call or an assignment
+            Some(Token::Ident(_)) => Statement::ExprStmt { expr: self.parse_expr()? },
+            Some(Token::Return) => {
                 // eat Return
                 self.tokens.next();
-                Some(
-                    Statement::Return { value: self.parse_expr() },
-                )
+                let value = match self.peek() {
+                    Some(Token::Semicolon) => None,
+                    _ => Some(self.parse_expr()?),
+                };
+                Statement::Return { value, is_tail_call: false }
             },
-            _ => return None,
+            Some(Token::Break) => Statement::Break { span: self.next_token()?.1 },
+            Some(Token::Continue) => Statement::Continue { span: self.next_token()?.1 },
+            _ => return Ok(None),
         };
 
-        assert!(matches!(self.tokens.next(), Some(Token::Semicolon)));
-        stmt
+        Ok(Some(stmt))
     }
 
-    fn parse_block_as_stmt_list(&mut self) -> Vec<Statement<'a>> {
-        assert!(matches!(self.tokens.next(), Some(Token::LeftBrace)), "expected '{{'");
+    fn expect_simple_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        match self.maybe_parse_simple_statement()? {
+            Some(stmt) => Ok(stmt),
+            None => {
+                let (tkn, span) = self.next_token()?;
+                Err(ParseError::UnexpectedToken(tkn, span))
+            },
+        }
+    }
 
-        let mut stmts = Vec::new();
-        while let Some(stmt) = self.maybe_parse_statement() {
-            stmts.push(stmt);
+    /// Parse `for (init; condition; step) { body }` or `for variable in iterable { body }`
+    fn parse_for(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        self.expect(Token::For)?;
+        if let Some(Token::Ident(_)) = self.peek() {
+            let (variable, span) = self.expect_name()?;
+            self.expect(Token::In)?;
+            return Ok(Statement::ForEach {
+                variable,
+                span,
+                iterable: self.parse_expr()?,
+                body: self.parse_block_as_stmt_list()?,
+            });
         }
+        self.expect(Token::LeftParen)?;
+        let init = self.expect_simple_statement()?;
+        self.expect(Token::Semicolon)?;
+        let condition = self.parse_expr()?;
+        self.expect(Token::Semicolon)?;
+        let step = self.expect_simple_statement()?;
+        self.expect(Token::RightParen)?;
 
-        assert!(matches!(self.tokens.next(), Some(Token::RightBrace)), "expected '}}'");
+        Ok(Statement::For {
+            init: Box::new(init),
+            condition,
+            step: Box::new(step),
+            body: self.parse_block_as_stmt_list()?,
+        })
+    }
 
-        stmts
+    fn parse_if(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        self.expect(Token::If)?;
+        let condition = self.parse_expr()?;
+        self.parse_if_blocks(condition)
     }
-}
 
-impl<'a, T: Iterator<Item=Token<'a>>> Iterator for ItemStream<'a, T> {
-    type Item = Item<'a>;
+    /// Parse the blocks of an if statement, after its condition
+    fn parse_if_blocks(&mut self, condition: Expr<'a>) -> Result<Statement<'a>, ParseError<'a>> {
+        let then_block = self.parse_block_as_stmt_list()?;
+        let else_block = if let Some(Token::Else) = self.peek() {
+            self.tokens.next();
+            if let Some(&(Token::If, if_span)) = self.tokens.peek() {
+                // `else if` is sugar for an else block containing just an if
+                Some(vec![self.nested(if_span, Self::parse_if)?])
+            } else {
+                Some(self.parse_block_as_stmt_list()?)
+            }
+        } else {
+            None
+        };
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let item = match self.tokens.next()? {
+        Ok(Statement::If { condition, then_block, else_block })
+    }
+
+    fn parse_block_as_stmt_list(&mut self) -> Result<Vec<Statement<'a>>, ParseError<'a>> {
+        let open_span = self.expect(Token::LeftBrace)?;
+
+        self.nested(open_span, |stream| {
+            let mut stmts = Vec::new();
+            loop {
+                match stream.maybe_parse_statement() {
+                    Ok(Some(stmt)) => stmts.push(stmt),
+                    Ok(None) => break,
+                    Err(e) => {
+                        if stream.recover(e, true)? {
+                            return Ok(stmts);
+                        }
+                    },
+                }
+            }
+
+            stream.expect(Token::RightBrace)?;
+
+            Ok(stmts)
+        })
+    }
+
+    /// Parse the raw contents of a string literal, splitting out any `${}` interpolations.
+    /// The expressions in them get spans in the source, offset from the literal's `span`.
+    fn parse_string_lit(&mut self, raw: &'a str, span: Span) -> Result<Expr<'a>, ParseError<'a>> {
+        let mut parts = Vec::new();
+        let mut text_start = 0;
+        let mut i = 0;
+        while let Some(ch) = raw[i..].chars().next() {
+            if ch == '\\' {
+                // skip the escaped char too, so `\$` doesn't start an interpolation
+                i += 1 + raw[i + 1..].chars().next().unwrap().len_utf8();
+                continue;
+            }
+            if !raw[i..].starts_with("${") {
+                i += ch.len_utf8();
+                continue;
+            }
+
+            if text_start < i {
+                parts.push(StringPart::Text(lex::unescape(&raw[text_start..i])));
+            }
+            // the lexer already checked that the interpolation is lexable and closed
+            let code_start = span.start + 1 + i + 2;
+            // boxed so that parsing an interpolation inside this one doesn't need yet another stream type
+            let tokens: Box<dyn Iterator<Item=(Token<'a>, Span)>> = Box::new(
+                lex::lex_tokens(&raw[i + 2..])
+                    .map_while(Result::ok)
+                    .map(move |(tkn, s)| (tkn, Span { start: s.start + code_start, end: s.end + code_start }))
+            );
+            // the interpolation is parsed by its own stream, but nested at the same depth
+            // and sharing names with the rest
+            let mut stream = ItemStream::new(tokens, std::mem::take(&mut self.interner), self.depth, self.max_depth);
+            let result = stream.parse_expr().and_then(|expr| Ok((expr, stream.next_token()?)));
+            self.interner = std::mem::take(&mut stream.interner);
+            match result? {
+                (expr, (Token::RightBrace, close)) => {
+                    parts.push(StringPart::Expr(expr));
+                    i = close.end - span.start - 1;
+                },
+                (_, (found, span)) => return Err(ParseError::ExpectedToken { expected: Token::RightBrace, found, span }),
+            }
+            text_start = i;
+        }
+
+        if parts.is_empty() {
+            return Ok(Expr::StringLit { value: lex::unescape(raw) });
+        }
+        if text_start < raw.len() {
+            parts.push(StringPart::Text(lex::unescape(&raw[text_start..])));
+        }
+        Ok(Expr::InterpolatedString { parts })
+    }
+
+    fn parse_type(&mut self) -> Result<Type, ParseError<'a>> {
+        let (name, span) = self.expect_ident()?;
+        Type::from_name(name).ok_or(ParseError::UnknownType(name, span))
+    }
+
+    /// Parse a function's parenthesized args, with their optional types, and its optional return type
+    fn parse_signature(&mut self) -> Result<Signature<'a>, ParseError<'a>> {
+        self.expect(Token::LeftParen)?;
+        let (arg_names, arg_types) = self.parse_comma_list(Token::RightParen, |s| {
+            let (name, _) = s.expect_name()?;
+            let ty = if s.tokens.next_if(|(tkn, _)| *tkn == Token::Colon).is_some() {
+                Some(s.parse_type()?)
+            } else {
+                None
+            };
+            Ok((name, ty))
+        })?.into_iter().unzip();
+        self.expect(Token::RightParen)?;
+
+        let return_type = if self.tokens.next_if(|(tkn, _)| *tkn == Token::Arrow).is_some() {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        Ok(Signature { arg_names, arg_types, return_type })
+    }
+
+    fn parse_item(&mut self, first: Token<'a>, first_span: Span) -> Result<Item<'a>, ParseError<'a>> {
+        let item = match first {
             Token::Begin => Item::EntryBlock {
-                body: self.parse_block_as_stmt_list(),
+                span: first_span,
+                body: self.parse_block_as_stmt_list()?,
             },
             Token::Func => {
-                let funcname = match self.tokens.next() {
-                    Some(Token::Ident(ident)) => ident,
-                    other => panic!("Unexpected token: {other:?}"),
-                };
-
-                assert!(matches!(self.tokens.next(), Some(Token::LeftParen)), "expected '('");
-                let mut arg_names = Vec::new();
-                loop {
-                    match self.tokens.next().unwrap() {
-                        Token::Ident(ident) => {
-                            arg_names.push(ident);
-                            if let Token::Comma = self.tokens.peek().unwrap() {
-                                self.tokens.next();
-                                assert!(matches!(self.tokens.peek(), Some(Token::Ident(_))), "expected identifier");
-                            }
-                        },
-                        Token::RightParen => break,
-                        other => panic!("unexpected token: {other:?}"),
-                    }
-                }
-                assert!(matches!(self.tokens.next(), Some(Token::LeftBrace)), "Expected '{{'");
-                let mut body = Vec::new();
-                while let Some(stmt) = self.maybe_parse_statement() {
-                    body.push(stmt);
-                }
-                match self.tokens.next() {
-                    Some(Token::RightBrace) => {},
-                    other => panic!("Expected '}}', found: {other:?}"),
-                }
+                let (funcname, name_span) = self.expect_name()?;
+                let Signature { arg_names, arg_types, return_type } = self.parse_signature()?;
 
                 Item::FuncDef {
                     name: funcname,
+                    name_span,
                     arg_names,
-                    body,
+                    arg_types,
+                    return_type,
+                    body: self.parse_block_as_stmt_list()?,
                 }
             },
-            tkn => panic!("Unexpected token {tkn:?}"),
+            Token::Var | Token::Const => {
+                let (variable, span) = self.expect_name()?;
+                self.expect(Token::Equals)?;
+                let value = self.parse_expr()?;
+                self.expect(Token::Semicolon)?;
+
+                Item::GlobalVar { variable, span, value, is_const: matches!(first, Token::Const) }
+            },
+            tkn => return Err(ParseError::UnexpectedToken(tkn, first_span)),
         };
 
-        Some(item)
+        Ok(item)
     }
 }
 
-#[derive(Debug)]
+/// The args and return type of a function definition or lambda
+struct Signature<'a> {
+    arg_names: Vec<Ident<'a>>,
+    arg_types: Vec<Option<Type>>,
+    return_type: Option<Type>,
+}
+
+/// The error for finding `found` where an identifier was required
+fn ident_error(found: Token<'_>, span: Span) -> ParseError<'_> {
+    match found.keyword() {
+        Some(kw) => ParseError::ReservedKeyword(kw, span),
+        None => ParseError::ExpectedIdent(found, span),
+    }
+}
+
+/// Parse the text of an integer literal, negated if `negative`
+fn parse_int(text: &str, negative: bool, span: Span) -> Result<i64, ParseError<'_>> {
+    let (radix, digits) = lex::split_radix(text);
+    let digits = digits.replace('_', "");
+    let parsed = if negative {
+        i64::from_str_radix(&format!("-{digits}"), radix)
+    } else {
+        i64::from_str_radix(&digits, radix)
+    };
+    parsed.map_err(|_| ParseError::IntegerOutOfRange(text, span))
+}
+
+/// Binding power shared by all the comparison operators, which don't chain
+pub(crate) const COMPARISON_POWER: u8 = 4;
+
+/// Binding power of `**`, the only binary operator that binds tighter than unary minus
+pub(crate) const POW_POWER: u8 = 7;
+
+/// Binding power of a binary operator, or `None` if `tkn` isn't one.
+/// Higher binds tighter.
+pub(crate) fn binop_power(tkn: &Token) -> Option<u8> {
+    match tkn {
+        Token::DotDot | Token::DotDotEquals => Some(1),
+        Token::OrOr => Some(2),
+        Token::AndAnd => Some(3),
+        Token::DoubleEquals | Token::NotEquals
+            | Token::Less | Token::Greater
+            | Token::LessEquals | Token::GreaterEquals => Some(COMPARISON_POWER),
+        Token::Plus | Token::Minus => Some(5),
+        Token::Star | Token::Slash | Token::Percent => Some(6),
+        Token::StarStar => Some(POW_POWER),
+        _ => None,
+    }
+}
+
+/// Split a binary operator expression into its operands and operator token
+pub(crate) fn split_binop<'e, 'a>(expr: &'e Expr<'a>) -> Option<(&'e Expr<'a>, Token<'static>, &'e Expr<'a>)> {
+    let (lhs, op, rhs) = match expr {
+        Expr::Add { lhs, rhs } => (lhs, Token::Plus, rhs),
+        Expr::Sub { lhs, rhs } => (lhs, Token::Minus, rhs),
+        Expr::Mul { lhs, rhs } => (lhs, Token::Star, rhs),
+        Expr::Div { lhs, rhs } => (lhs, Token::Slash, rhs),
+        Expr::Mod { lhs, rhs } => (lhs, Token::Percent, rhs),
+        Expr::Pow { lhs, rhs } => (lhs, Token::StarStar, rhs),
+        Expr::Eq { lhs, rhs } => (lhs, Token::DoubleEquals, rhs),
+        Expr::NotEq { lhs, rhs } => (lhs, Token::NotEquals, rhs),
+        Expr::Less { lhs, rhs } => (lhs, Token::Less, rhs),
+        Expr::Greater { lhs, rhs } => (lhs, Token::Greater, rhs),
+        Expr::LessEq { lhs, rhs } => (lhs, Token::LessEquals, rhs),
+        Expr::GreaterEq { lhs, rhs } => (lhs, Token::GreaterEquals, rhs),
+        Expr::And { lhs, rhs } => (lhs, Token::AndAnd, rhs),
+        Expr::Or { lhs, rhs } => (lhs, Token::OrOr, rhs),
+        Expr::Range { lhs, rhs } => (lhs, Token::DotDot, rhs),
+        Expr::RangeInclusive { lhs, rhs } => (lhs, Token::DotDotEquals, rhs),
+        _ => return None,
+    };
+    Some((lhs, op, rhs))
+}
+
+/// Whether `a op b op c` groups as `a op (b op c)`. Only `**` does; all
+/// the other binary operators are left-associative.
+pub(crate) fn is_right_associative(tkn: &Token) -> bool {
+    matches!(tkn, Token::StarStar)
+}
+
+fn compound_assign_op(tkn: &Token) -> Option<CompoundOp> {
+    match tkn {
+        Token::PlusEquals => Some(CompoundOp::Add),
+        Token::MinusEquals => Some(CompoundOp::Sub),
+        Token::StarEquals => Some(CompoundOp::Mul),
+        Token::SlashEquals => Some(CompoundOp::Div),
+        _ => None,
+    }
+}
+
+/// Whether `expr` can be assigned to: a variable, or an element of something that can be
+pub(crate) fn is_assignable(mut expr: &Expr) -> bool {
+    while let Expr::Index { array, .. } = expr {
+        expr = array;
+    }
+    matches!(expr, Expr::VarRef { .. })
+}
+
+/// Split the target of an `Expr::Assign` into the variable it's rooted at, that
+/// variable's slot, and the indexes applied to it in the order they're written
+pub(crate) fn split_assign_target<'e, 'a>(mut target: &'e Expr<'a>) -> (&'e Ident<'a>, Slot, Vec<&'e Expr<'a>>) {
+    let mut indexes = Vec::new();
+    while let Expr::Index { array, index } = target {
+        indexes.push(&**index);
+        target = array;
+    }
+    let Expr::VarRef { variable, slot, .. } = target else {
+        unreachable!("{target:?} can't be assigned to");
+    };
+    indexes.reverse();
+    (variable, *slot, indexes)
+}
+
+pub(crate) fn make_binop<'a>(op: Token<'a>, lhs: Expr<'a>, rhs: Expr<'a>) -> Expr<'a> {
+    let lhs = Box::new(lhs);
+    let rhs = Box::new(rhs);
+    match op {
+        Token::Plus => Expr::Add { lhs, rhs },
+        Token::Minus => Expr::Sub { lhs, rhs },
+        Token::Star => Expr::Mul { lhs, rhs },
+        Token::Slash => Expr::Div { lhs, rhs },
+        Token::Percent => Expr::Mod { lhs, rhs },
+        Token::StarStar => Expr::Pow { lhs, rhs },
+        Token::DoubleEquals => Expr::Eq { lhs, rhs },
+        Token::NotEquals => Expr::NotEq { lhs, rhs },
+        Token::Less => Expr::Less { lhs, rhs },
+        Token::Greater => Expr::Greater { lhs, rhs },
+        Token::LessEquals => Expr::LessEq { lhs, rhs },
+        Token::GreaterEquals => Expr::GreaterEq { lhs, rhs },
+        Token::AndAnd => Expr::And { lhs, rhs },
+        Token::OrOr => Expr::Or { lhs, rhs },
+        Token::DotDot => Expr::Range { lhs, rhs },
+        Token::DotDotEquals => Expr::RangeInclusive { lhs, rhs },
+        other => unreachable!("{other:?} is not a binary operator"),
+    }
+}
+
+impl<'a, T: Iterator<Item=(Token<'a>, Span)>> Iterator for ItemStream<'a, T> {
+    type Item = Result<Item<'a>, ParseError<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_empty_statements();
+        let (first, span) = self.tokens.next()?;
+        Some(self.parse_item(first, span))
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr<'a> {
     IntLit {
-        value: u32,
+        value: i64,
+    },
+
+    FloatLit {
+        /// Infinity if the literal is too big for a float, which JSON writes as null
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::json::deserialize_float_lit"))]
+        value: f64,
+    },
+
+    BoolLit {
+        value: bool,
+    },
+
+    StringLit {
+        value: String,
+    },
+
+    /// A string literal with `${}` interpolations in it, like `"x is ${x}"`
+    InterpolatedString {
+        parts: Vec<StringPart<'a>>,
     },
 
     VarRef {
-        variable: &'a str,
+        variable: Ident<'a>,
+        span: Span,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        slot: Slot,
     },
 
     Add {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    Sub {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    Mul {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    /// Truncates when dividing ints, and errors at runtime if an int `rhs` is 0
+    Div {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    /// Remainder, with the sign of `lhs`
+    Mod {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    /// `lhs ** rhs`. An int raised to an int stays an int, so a negative
+    /// int exponent is an error rather than giving a fraction. Unlike the
+    /// other binary operators, it binds tighter than unary minus: `-2 ** 2` is -4.
+    Pow {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    Eq {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
         lhs: Box<Expr<'a>>,
         rhs: Box<Expr<'a>>,
     },
 
+    NotEq {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    Less {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    Greater {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    LessEq {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    GreaterEq {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    /// Short-circuiting: `rhs` is only evaluated if `lhs` is true
+    And {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    /// Short-circuiting: `rhs` is only evaluated if `lhs` is false
+    Or {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    /// `lhs..rhs`: an array of the ints from `lhs` up to but not including `rhs`.
+    /// A for loop over one goes through the ints without building the array, so
+    /// only a range used as a value is limited to `interp::MAX_RANGE_LEN` ints.
+    Range {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    /// `lhs..=rhs`: like `Range`, but including `rhs`
+    RangeInclusive {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_lhs"))]
+        lhs: Box<Expr<'a>>,
+        rhs: Box<Expr<'a>>,
+    },
+
+    Neg {
+        operand: Box<Expr<'a>>,
+    },
+
+    Not {
+        operand: Box<Expr<'a>>,
+    },
+
+    /// Statements with an optional trailing expression that gives the
+    /// block's value, or unit if there isn't one. Variables declared
+    /// in the block are scoped to it.
+    Block {
+        body: Vec<Statement<'a>>,
+        value: Option<Box<Expr<'a>>>,
+    },
+
+    ArrayLit {
+        elements: Vec<Expr<'a>>,
+    },
+
+    /// `#{key: value, ...}`. A map literal can't start with a plain `{`, since
+    /// that starts a block expression. Keys must be strings, and an entry
+    /// replaces any earlier one with the same key.
+    MapLit {
+        entries: Vec<(Expr<'a>, Expr<'a>)>,
+    },
+
+    /// Indexing an array or a map. Errors at runtime if `index` is out of
+    /// bounds, or isn't one of the map's keys.
+    Index {
+        array: Box<Expr<'a>>,
+        index: Box<Expr<'a>>,
+    },
+
+    /// `if condition then then_expr else else_expr`. Only the taken branch
+    /// is evaluated. `else_expr` extends as far right as possible, and
+    /// `condition` must be a bool, like a `Statement::If`'s.
+    IfExpr {
+        condition: Box<Expr<'a>>,
+        then_expr: Box<Expr<'a>>,
+        else_expr: Box<Expr<'a>>,
+    },
+
+    /// `func(args) { body }`, an anonymous function. Evaluating it makes a closure
+    /// that captures a copy of every local in scope, by value, so later changes to
+    /// them aren't seen by the closure. The body can read its copies, but assigning
+    /// to one is `RuntimeError::AssignToCaptured`, since it wouldn't change the
+    /// local. Globals aren't captured, and neither is a variable whose
+    /// initializer the lambda is in, so a lambda can't call itself by name.
+    Lambda(Lambda<'a>),
+
+    /// `target = value`, which assigns `value` and also gives it. `target` is a
+    /// `VarRef`, or an `Index` into something that could be a target itself, like
+    /// `grid[y][x]`. Assigning to an element errors if its index is out of bounds,
+    /// but a map gets the key added if it isn't one already.
+    ///
+    /// With an `op`, it's a compound assignment like `target += value`, which
+    /// reads the target, applies `op` to it and `value`, and assigns the result.
+    /// The target's indexes are only evaluated once, for both the read and the write.
+    Assign {
+        target: Box<Expr<'a>>,
+        op: Option<CompoundOp>,
+        value: Box<Expr<'a>>,
+    },
+
+    /// `callee(args)`. The callee is evaluated first, and then the args from left
+    /// to right. It's usually a `VarRef` naming a function, but it can be anything
+    /// that gives a function value.
     FuncCall {
-        func_name: &'a str,
+        callee: Box<Expr<'a>>,
         args: Vec<Expr<'a>>,
+        /// Span of the first token of `callee`
+        span: Span,
     },
 }
 
-#[derive(Debug)]
+/// The signature and body of an `Expr::Lambda` or `Statement::FuncDef`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lambda<'a> {
+    pub arg_names: Vec<Ident<'a>>,
+    /// The optional annotation on each arg, in the same order as `arg_names`
+    pub arg_types: Vec<Option<Type>>,
+    pub return_type: Option<Type>,
+    pub body: Vec<Statement<'a>>,
+}
+
+impl Lambda<'_> {
+    fn to_owned(&self) -> Lambda<'static> {
+        Lambda {
+            arg_names: self.arg_names.iter().map(|arg| arg.to_owned()).collect(),
+            arg_types: self.arg_types.clone(),
+            return_type: self.return_type,
+            body: block_to_owned(&self.body),
+        }
+    }
+}
+
+/// Where the variable a name refers to is kept at runtime. The parser leaves
+/// every slot unresolved, and the interpreter resolves them before running.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    #[default]
+    Unresolved,
+    /// An index into the frame of locals of the enclosing function call, or of the begin block
+    Local(usize),
+    /// A local of a function whose body this is nested in, for the body of a lambda
+    /// or of a function defined in a block. `level` is how deeply that function is
+    /// nested, where a top-level function or the begin block is 0, and `index` is
+    /// the local's slot in its frame.
+    Outer { level: usize, index: usize },
+    Global,
+    /// Not a variable, but the user function or builtin with that name. Only names
+    /// that aren't variables resolve to this, so variables shadow functions. A
+    /// call of a name that's neither resolves to this as well, and fails when it runs.
+    Function,
+}
+
+/// A piece of an `Expr::InterpolatedString`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StringPart<'a> {
+    /// Literal text, with its escapes already resolved
+    Text(String),
+    /// A `${}` interpolation
+    Expr(Expr<'a>),
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Statement<'a> {
     VarDeclaration {
-        variable: &'a str,
+        variable: Ident<'a>,
+        /// Span of `variable`
+        span: Span,
         value: Expr<'a>,
+        /// Declared with `const` rather than `var`, so it can't be reassigned
+        is_const: bool,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        slot: Slot,
     },
 
-    Assignment {
-        variable: &'a str,
-        value: Expr<'a>,
+    /// `var a = 1, b = a + 1;`, declaring each variable in turn as if they were
+    /// separate statements, so an initializer can use the variables before it.
+    /// The declarations are all `VarDeclaration`s, and all `const` or all not.
+    MultiVarDeclaration {
+        declarations: Vec<Statement<'a>>,
     },
 
+    /// Functions without a `return` that runs return unit, and so does a bare `return;`
     Return {
-        value: Expr<'a>,
+        /// `None` for a bare `return;`
+        value: Option<Expr<'a>>,
+        /// Whether this is `return f(...)` directly in a statement of `f`'s own
+        /// body, at any depth of if, while and for bodies but not inside a block
+        /// expression. The interpreter works this out before running, and runs
+        /// these calls by reusing the current call's frame instead of recursing.
+        #[cfg_attr(feature = "serde", serde(skip))]
+        is_tail_call: bool,
+    },
+
+    /// Leaves the innermost loop whose body it's in. Block expressions and
+    /// lambdas in a loop body aren't in the loop, for this and for `Continue`.
+    Break {
+        span: Span,
+    },
+
+    /// Skips the rest of the innermost loop body it's in, going on to the next
+    /// iteration. In a for loop, the step still runs.
+    Continue {
+        span: Span,
+    },
+
+    /// An expression evaluated only for its side effects
+    ExprStmt {
+        expr: Expr<'a>,
+    },
+
+    /// `func name(args) { body }` in a block, a function that's only visible in
+    /// that block. Such functions are in scope all through their block, the way
+    /// top-level functions are everywhere, so they can call ones defined after
+    /// them. Called by name, the body sees the locals in scope at the definition
+    /// as they are when it runs, and can assign to them. Used as a value, it's
+    /// a closure with copies of them, like a lambda's.
+    FuncDef {
+        name: Ident<'a>,
+        name_span: Span,
+        func: Lambda<'a>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        slot: Slot,
+        /// Whether the function can be called as soon as its block starts running.
+        /// One that uses a local declared earlier in its block can't be called
+        /// until its definition has run, since the local doesn't exist until then.
+        /// This is worked out along with slots.
+        #[cfg_attr(feature = "serde", serde(skip))]
+        defined_on_entry: bool,
+    },
+
+    /// There's no truthiness: `condition` must be a bool, and anything else, like
+    /// `if 1 { }`, is a type error at runtime. The same goes for every other
+    /// condition, and for the operands of `!`, `&&`, and `||`.
+    If {
+        condition: Expr<'a>,
+        then_block: Vec<Statement<'a>>,
+        else_block: Option<Vec<Statement<'a>>>,
+    },
+
+    /// `condition` must be a bool, like an `If`'s
+    While {
+        condition: Expr<'a>,
+        body: Vec<Statement<'a>>,
+    },
+
+    /// Variables declared in `init` are scoped to the loop, and `condition`
+    /// must be a bool, like an `If`'s
+    For {
+        init: Box<Statement<'a>>,
+        condition: Expr<'a>,
+        step: Box<Statement<'a>>,
+        body: Vec<Statement<'a>>,
+    },
+
+    /// `for variable in iterable { body }`. `iterable` must be an array, and
+    /// `variable` is a new variable in each iteration, holding the next element.
+    /// An array changed by the body is still iterated as it was before the loop.
+    ForEach {
+        variable: Ident<'a>,
+        span: Span,
+        iterable: Expr<'a>,
+        body: Vec<Statement<'a>>,
     },
 }
 
 /// A top-level thing
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Item<'a> {
     EntryBlock {
+        /// Span of the `begin` keyword
+        span: Span,
         body: Vec<Statement<'a>>,
     },
 
+    /// Functions are all defined before anything runs, so every top-level function
+    /// is in scope everywhere, regardless of definition order (mutual recursion works)
     FuncDef {
-        name: &'a str,
-        arg_names: Vec<&'a str>,
+        name: Ident<'a>,
+        name_span: Span,
+        arg_names: Vec<Ident<'a>>,
+        /// The optional annotation on each arg, in the same order as `arg_names`
+        arg_types: Vec<Option<Type>>,
+        return_type: Option<Type>,
         body: Vec<Statement<'a>>,
     },
+
+    /// A top-level `var` or `const`, visible to all functions and the begin block
+    GlobalVar {
+        variable: Ident<'a>,
+        /// Span of `variable`
+        span: Span,
+        value: Expr<'a>,
+        is_const: bool,
+    },
+}
+
+/// The operator of a compound assignment, like `+=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CompoundOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl CompoundOp {
+    /// The binary operator it applies, like `+` for `+=`
+    pub fn binop(self) -> Token<'static> {
+        match self {
+            Self::Add => Token::Plus,
+            Self::Sub => Token::Minus,
+            Self::Mul => Token::Star,
+            Self::Div => Token::Slash,
+        }
+    }
+}
+
+/// A type that function args and return values can be annotated with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    String,
+    Array,
+    Map,
+    Unit,
+    Function,
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "int" => Some(Self::Int),
+            "float" => Some(Self::Float),
+            "bool" => Some(Self::Bool),
+            "string" => Some(Self::String),
+            "array" => Some(Self::Array),
+            "map" => Some(Self::Map),
+            "unit" => Some(Self::Unit),
+            "function" => Some(Self::Function),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Int => "int",
+            Self::Float => "float",
+            Self::Bool => "bool",
+            Self::String => "string",
+            Self::Array => "array",
+            Self::Map => "map",
+            Self::Unit => "unit",
+            Self::Function => "function",
+        }
+    }
+
+    /// The name with "a" or "an" in front, for error messages
+    pub fn with_article(&self) -> &'static str {
+        match self {
+            Self::Int => "an int",
+            Self::Float => "a float",
+            Self::Bool => "a bool",
+            Self::String => "a string",
+            Self::Array => "an array",
+            Self::Map => "a map",
+            Self::Unit => "a unit",
+            Self::Function => "a function",
+        }
+    }
+}
+
+// Conversions to an AST that doesn't borrow from the source, so it can be kept
+// (or run) after the source is gone. Names are the only thing borrowed, so
+// this is a deep copy with each `Ident` swapped for one that owns its name.
+
+impl Item<'_> {
+    pub fn to_owned(&self) -> Item<'static> {
+        match self {
+            Item::EntryBlock { span, body } => Item::EntryBlock { span: *span, body: block_to_owned(body) },
+            Item::FuncDef { name, name_span, arg_names, arg_types, return_type, body } => Item::FuncDef {
+                name: name.to_owned(),
+                name_span: *name_span,
+                arg_names: arg_names.iter().map(|arg| arg.to_owned()).collect(),
+                arg_types: arg_types.clone(),
+                return_type: *return_type,
+                body: block_to_owned(body),
+            },
+            Item::GlobalVar { variable, span, value, is_const } => Item::GlobalVar {
+                variable: variable.to_owned(),
+                span: *span,
+                value: value.to_owned(),
+                is_const: *is_const,
+            },
+        }
+    }
+}
+
+impl ReplInput<'_> {
+    pub fn to_owned(&self) -> ReplInput<'static> {
+        ReplInput { body: block_to_owned(&self.body), value: self.value.as_ref().map(Expr::to_owned) }
+    }
+}
+
+fn block_to_owned(stmts: &[Statement]) -> Vec<Statement<'static>> {
+    stmts.iter().map(Statement::to_owned).collect()
+}
+
+impl Statement<'_> {
+    pub fn to_owned(&self) -> Statement<'static> {
+        match self {
+            Statement::VarDeclaration { variable, span, value, is_const, slot } => Statement::VarDeclaration {
+                variable: variable.to_owned(),
+                span: *span,
+                value: value.to_owned(),
+                is_const: *is_const,
+                slot: *slot,
+            },
+            Statement::MultiVarDeclaration { declarations } => Statement::MultiVarDeclaration {
+                declarations: block_to_owned(declarations),
+            },
+            Statement::Return { value, is_tail_call } => {
+                Statement::Return { value: value.as_ref().map(Expr::to_owned), is_tail_call: *is_tail_call }
+            },
+            Statement::FuncDef { name, name_span, func, slot, defined_on_entry } => Statement::FuncDef {
+                name: name.to_owned(),
+                name_span: *name_span,
+                func: func.to_owned(),
+                slot: *slot,
+                defined_on_entry: *defined_on_entry,
+            },
+            Statement::Break { span } => Statement::Break { span: *span },
+            Statement::Continue { span } => Statement::Continue { span: *span },
+            Statement::ExprStmt { expr } => Statement::ExprStmt { expr: expr.to_owned() },
+            Statement::If { condition, then_block, else_block } => Statement::If {
+                condition: condition.to_owned(),
+                then_block: block_to_owned(then_block),
+                else_block: else_block.as_deref().map(block_to_owned),
+            },
+            Statement::While { condition, body } => Statement::While {
+                condition: condition.to_owned(),
+                body: block_to_owned(body),
+            },
+            Statement::For { init, condition, step, body } => Statement::For {
+                init: Box::new(Statement::to_owned(init)),
+                condition: condition.to_owned(),
+                step: Box::new(Statement::to_owned(step)),
+                body: block_to_owned(body),
+            },
+            Statement::ForEach { variable, span, iterable, body } => Statement::ForEach {
+                variable: variable.to_owned(),
+                span: *span,
+                iterable: iterable.to_owned(),
+                body: block_to_owned(body),
+            },
+        }
+    }
+}
+
+impl Expr<'_> {
+    pub fn to_owned(&self) -> Expr<'static> {
+        if let Some((lhs, op, rhs)) = split_binop(self) {
+            return make_binop(op, lhs.to_owned(), rhs.to_owned());
+        }
+
+        let boxed = |expr: &Expr| Box::new(expr.to_owned());
+        match self {
+            Expr::IntLit { value } => Expr::IntLit { value: *value },
+            Expr::FloatLit { value } => Expr::FloatLit { value: *value },
+            Expr::BoolLit { value } => Expr::BoolLit { value: *value },
+            Expr::StringLit { value } => Expr::StringLit { value: value.clone() },
+            Expr::InterpolatedString { parts } => Expr::InterpolatedString {
+                parts: parts.iter()
+                    .map(|part| match part {
+                        StringPart::Text(text) => StringPart::Text(text.clone()),
+                        StringPart::Expr(expr) => StringPart::Expr(expr.to_owned()),
+                    })
+                    .collect(),
+            },
+            Expr::VarRef { variable, span, slot } => Expr::VarRef { variable: variable.to_owned(), span: *span, slot: *slot },
+            Expr::Neg { operand } => Expr::Neg { operand: boxed(operand) },
+            Expr::Not { operand } => Expr::Not { operand: boxed(operand) },
+            Expr::Block { body, value } => Expr::Block {
+                body: block_to_owned(body),
+                value: value.as_deref().map(boxed),
+            },
+            Expr::ArrayLit { elements } => Expr::ArrayLit { elements: elements.iter().map(Expr::to_owned).collect() },
+            Expr::MapLit { entries } => Expr::MapLit {
+                entries: entries.iter().map(|(key, value)| (key.to_owned(), value.to_owned())).collect(),
+            },
+            Expr::Index { array, index } => Expr::Index { array: boxed(array), index: boxed(index) },
+            Expr::IfExpr { condition, then_expr, else_expr } => Expr::IfExpr {
+                condition: boxed(condition),
+                then_expr: boxed(then_expr),
+                else_expr: boxed(else_expr),
+            },
+            Expr::Lambda(lambda) => Expr::Lambda(lambda.to_owned()),
+            Expr::Assign { target, op, value } => {
+                Expr::Assign { target: boxed(target), op: *op, value: boxed(value) }
+            },
+            Expr::FuncCall { callee, args, span } => Expr::FuncCall {
+                callee: boxed(callee),
+                args: args.iter().map(Expr::to_owned).collect(),
+                span: *span,
+            },
+            _ => unreachable!("binary operators are handled above"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Result<Vec<Item<'_>>, ParseError<'_>> {
+        let tokens = lex::lex_tokens(src).collect::<Result<Vec<_>, _>>().unwrap();
+        parse_items(tokens.into_iter()).collect()
+    }
+
+    /// The statements of the begin block in `src`
+    fn begin_body(src: &str) -> Vec<Statement<'_>> {
+        match parse(src).unwrap().pop() {
+            Some(Item::EntryBlock { body, .. }) => body,
+            other => panic!("expected a begin block, not {other:?}"),
+        }
+    }
+
+    #[test]
+    fn binary_operators_group_by_precedence() {
+        let body = begin_body("begin { var a = 2 + 3 * 4; var b = 10 - 2 - 3; var c = 2 ** 3 ** 2; var d = (1 + 2) * 3; }");
+        let exprs: Vec<&Expr> = body.iter().map(|stmt| match stmt {
+            Statement::VarDeclaration { value, .. } => value,
+            other => panic!("expected a declaration, not {other:?}"),
+        }).collect();
+        assert!(matches!(exprs[0], Expr::Add { rhs, .. } if matches!(**rhs, Expr::Mul { .. })));
+        assert!(matches!(exprs[1], Expr::Sub { lhs, .. } if matches!(**lhs, Expr::Sub { .. })));
+        assert!(matches!(exprs[2], Expr::Pow { rhs, .. } if matches!(**rhs, Expr::Pow { .. })));
+        assert!(matches!(exprs[3], Expr::Mul { lhs, .. } if matches!(**lhs, Expr::Add { .. })));
+    }
+
+    #[test]
+    fn stray_paren_is_reported_where_it_is() {
+        let err = parse("begin { var x = 1); }").unwrap_err();
+        assert!(matches!(err, ParseError::ExpectedToken { found: Token::RightParen, .. }), "{err:?}");
+        assert_eq!(err.span(), Some(Span { start: 17, end: 18 }));
+    }
+
+    #[test]
+    fn int_literals_have_to_fit_in_an_int() {
+        assert!(matches!(parse("begin { var x = 999999999999999999999; }"), Err(ParseError::IntegerOutOfRange("999999999999999999999", _))));
+        assert!(matches!(parse("begin { var x = 0x10000000000000000; }"), Err(ParseError::IntegerOutOfRange(..))));
+        // the smallest int is only a literal when it's negated
+        assert!(parse("begin { var x = -9223372036854775808; }").is_ok());
+        assert!(matches!(parse("begin { var x = 9223372036854775808; }"), Err(ParseError::IntegerOutOfRange(..))));
+    }
+
+    #[test]
+    fn comparisons_dont_chain() {
+        let err = parse("begin { var x = 1 < 2 < 3; }").unwrap_err();
+        assert!(matches!(err, ParseError::ChainedComparison(Token::Less, Span { start: 22, .. })), "{err:?}");
+        assert!(parse("begin { var x = (1 < 2) == true; }").is_ok());
+    }
+
+    #[test]
+    fn keywords_cant_be_names() {
+        assert!(matches!(parse("begin { var if = 1; }"), Err(ParseError::ReservedKeyword("if", _))));
+        assert!(matches!(parse("func while() { } begin { }"), Err(ParseError::ReservedKeyword("while", _))));
+        assert!(matches!(parse("func f(return) { } begin { }"), Err(ParseError::ReservedKeyword("return", _))));
+    }
+
+    #[test]
+    fn trailing_commas_and_stray_semicolons() {
+        assert!(parse("func f(a, b,) { return a; } begin { ; var x = f(1, 2,);; var y = [1, 2,]; }").is_ok());
+        assert!(parse("begin { f(,); }").is_err());
+    }
+
+    #[test]
+    fn unknown_type_annotations() {
+        assert!(matches!(parse("func f(a: integer) { } begin { }"), Err(ParseError::UnknownType("integer", _))));
+        assert!(matches!(parse("func f() -> nope { } begin { }"), Err(ParseError::UnknownType("nope", _))));
+    }
+
+    #[test]
+    fn recovering_reports_every_error() {
+        let src = "func f() { var = 1; return 2; } begin { var x = ; var y = 3; }";
+        let tokens = lex::lex_tokens(src).collect::<Result<Vec<_>, _>>().unwrap();
+        let (items, errors) = parse_items_recovering(tokens.into_iter());
+        assert_eq!(items.len(), 2);
+        let spans: Vec<usize> = errors.iter().map(|e| e.span().unwrap().start).collect();
+        assert_eq!(spans, [15, 48]);
+    }
+
+    #[test]
+    fn repl_lines_are_statements_or_expressions() {
+        let repl_input = |src| {
+            let tokens = lex::lex_tokens(src).collect::<Result<Vec<_>, _>>().unwrap();
+            parse_repl_input(tokens.into_iter(), &mut Interner::default())
+        };
+        for src in ["if a then 1 else 2", "(1 + 2) * 3", "-x", "!b", "[1, 2][0]", "#{\"k\": 1}[\"k\"]", "\"s\" + t", "func(y) { return y; }(1)", "f(1)", "x"] {
+            let input = repl_input(src).unwrap_or_else(|e| panic!("{src}: {e}"));
+            assert!(input.body.is_empty() && input.value.is_some(), "{src}: {input:?}");
+        }
+        for src in ["var x = 1;", "x = 2;", "f(1);", "if a { }", "while false { }", "func g() { }"] {
+            let input = repl_input(src).unwrap_or_else(|e| panic!("{src}: {e}"));
+            assert!(input.body.len() == 1 && input.value.is_none(), "{src}: {input:?}");
+        }
+        let input = repl_input("var x = 1; x + 1").unwrap();
+        assert!(input.body.len() == 1 && matches!(input.value, Some(Expr::Add { .. })));
+        assert!(repl_input("1 + ").is_err());
+        assert!(repl_input("1 2").is_err());
+    }
 }
@@ -0,0 +1,516 @@
+use crate::ast::{self, Expr, Lambda, Slot, Statement, StringPart};
+use crate::interp::Program;
+use crate::lex::Span;
+use std::collections::HashMap;
+
+/// A mistake found in a program without running it
+#[derive(Debug)]
+pub enum SemanticError {
+    /// A variable that isn't declared in any enclosing scope, or as a global
+    UndefinedVariable {
+        name: String,
+        span: Span,
+    },
+    UndefinedFunction {
+        name: String,
+        /// Where it was called
+        span: Span,
+    },
+    /// A function is called with the wrong number of args
+    ArityMismatch {
+        func: String,
+        expected: usize,
+        got: usize,
+        /// Where it was called
+        span: Span,
+    },
+    /// A `break` or `continue` that isn't in a loop body
+    NotInLoop {
+        keyword: &'static str,
+        span: Span,
+    },
+    /// A variable or function declared again in a scope that already has one by
+    /// that name, with the span of the second declaration
+    Redeclaration {
+        name: String,
+        span: Span,
+    },
+}
+
+impl SemanticError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UndefinedVariable { span, .. }
+                | Self::UndefinedFunction { span, .. }
+                | Self::ArityMismatch { span, .. }
+                | Self::NotInLoop { span, .. }
+                | Self::Redeclaration { span, .. } => *span,
+        }
+    }
+}
+
+impl std::fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UndefinedVariable { name, span } => {
+                write!(f, "variable {name} is not defined (used at byte {})", span.start)
+            },
+            Self::UndefinedFunction { name, span } => {
+                write!(f, "no definition for func {name} (called at byte {})", span.start)
+            },
+            Self::ArityMismatch { func, expected, got, span } => {
+                write!(f, "func {func} takes {expected} args, but is called with {got} at byte {}", span.start)
+            },
+            Self::NotInLoop { keyword, span } => write!(f, "{keyword} at byte {} isn't in a loop", span.start),
+            Self::Redeclaration { name, span } => {
+                write!(f, "redeclaration of variable {name} (at byte {})", span.start)
+            },
+        }
+    }
+}
+
+/// Something suspicious in a program that doesn't stop it from running
+#[derive(Debug)]
+pub enum Warning {
+    /// A `var` or `const` that is never read. Assigning to it doesn't count.
+    UnusedVariable {
+        name: String,
+        /// Where it was declared
+        span: Span,
+    },
+}
+
+impl Warning {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UnusedVariable { span, .. } => *span,
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnusedVariable { name, span } => {
+                write!(f, "variable {name} is never read (declared at byte {})", span.start)
+            },
+        }
+    }
+}
+
+/// Check every function call in `program`, including ones that would never
+/// run, against the builtins and the program's functions, and check that
+/// every variable used is declared somewhere in scope
+pub fn check_program(program: &Program) -> Result<(), Vec<SemanticError>> {
+    let errors = Checker::run(program).errors;
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Find things in `program` that are probably mistakes, but not errors, in the order they're in the source
+pub fn lint_program(program: &Program) -> Vec<Warning> {
+    Checker::run(program).warnings
+}
+
+#[derive(Debug)]
+struct Declaration {
+    /// `None` for function args
+    span: Option<Span>,
+    /// Whether it's warned about if it's never read, which loop variables, args
+    /// and functions defined in blocks aren't
+    is_linted: bool,
+    read: bool,
+}
+
+struct Checker<'p, 'a> {
+    program: &'p Program<'a>,
+    errors: Vec<SemanticError>,
+    warnings: Vec<Warning>,
+    /// The variables declared so far in each scope, innermost last
+    scopes: Vec<HashMap<&'p str, Declaration>>,
+    /// Whether what's being checked is in a loop body, so it can `break` and `continue`
+    in_loop: bool,
+}
+
+impl<'p, 'a> Checker<'p, 'a> {
+    fn run(program: &'p Program<'a>) -> Self {
+        let mut checker = Self { program, errors: Vec::new(), warnings: Vec::new(), scopes: Vec::new(), in_loop: false };
+
+        checker.with_scope(|checker| {
+            // globals are initialized in order, so each initializer only sees the ones before it
+            for var in &program.global_vars {
+                checker.check_expr(&var.value);
+                checker.declare(var.name.name(), Some(var.span), true);
+            }
+            for func in program.global.functions.values() {
+                checker.with_scope(|checker| {
+                    for arg in &func.arg_names {
+                        checker.declare(arg.name(), None, false);
+                    }
+                    checker.check_block(&func.body);
+                });
+            }
+            checker.check_scoped_block(&program.begin_body);
+        });
+
+        // functions and scopes are hash maps, so sort by where in the source
+        // each problem is, to report them in the same order every time
+        checker.errors.sort_by_key(|e| e.span().start);
+        checker.warnings.sort_by_key(|w| w.span().start);
+        checker
+    }
+
+    /// Declare `varname` in the innermost scope. A declaration with a span that
+    /// redeclares a name in that scope is an error, and reads go to the first one.
+    fn declare(&mut self, varname: &'p str, span: Option<Span>, is_linted: bool) {
+        let scope = self.scopes.last_mut().unwrap();
+        match (scope.get(varname), span) {
+            (Some(_), Some(span)) => {
+                self.errors.push(SemanticError::Redeclaration { name: varname.to_string(), span });
+            },
+            (Some(_), None) => {},
+            (None, _) => {
+                scope.insert(varname, Declaration { span, is_linted, read: false });
+            },
+        }
+    }
+
+    /// The declaration of `varname` in the innermost scope that has one. If none
+    /// do, this is an error, and `span` is where the variable was used.
+    fn find_declaration(&mut self, varname: &str, span: Span) -> Option<&mut Declaration> {
+        let decl = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(varname));
+        if decl.is_none() {
+            self.errors.push(SemanticError::UndefinedVariable { name: varname.to_string(), span });
+        }
+        decl
+    }
+
+    /// Run `f` with a new innermost scope, which is dropped afterwards
+    fn with_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        for (name, decl) in self.scopes.pop().unwrap() {
+            if let Declaration { span: Some(span), is_linted: true, read: false } = decl {
+                self.warnings.push(Warning::UnusedVariable { name: name.to_string(), span });
+            }
+        }
+    }
+
+    /// Run `f` with `in_loop` set to `in_loop`, restoring it afterwards
+    fn with_in_loop(&mut self, in_loop: bool, f: impl FnOnce(&mut Self)) {
+        let outer = std::mem::replace(&mut self.in_loop, in_loop);
+        f(self);
+        self.in_loop = outer;
+    }
+
+    fn check_block(&mut self, stmts: &'p [Statement<'a>]) {
+        // functions defined in the block are in scope all through it. They're
+        // called rather than read, so they aren't linted.
+        for stmt in stmts {
+            if let Statement::FuncDef { name, name_span, .. } = stmt {
+                self.declare(name.name(), Some(*name_span), false);
+            }
+        }
+        for stmt in stmts {
+            self.check_statement(stmt);
+        }
+    }
+
+    fn check_lambda(&mut self, lambda: &'p Lambda<'a>) {
+        self.with_scope(|checker| {
+            for arg in &lambda.arg_names {
+                checker.declare(arg.name(), None, false);
+            }
+            checker.with_in_loop(false, |checker| checker.check_block(&lambda.body));
+        });
+    }
+
+    fn check_scoped_block(&mut self, stmts: &'p [Statement<'a>]) {
+        self.with_scope(|checker| checker.check_block(stmts));
+    }
+
+    fn check_statement(&mut self, stmt: &'p Statement<'a>) {
+        match stmt {
+            Statement::VarDeclaration { variable, span, value, .. } => {
+                self.check_expr(value);
+                self.declare(variable.name(), Some(*span), true);
+            },
+            Statement::MultiVarDeclaration { declarations } => self.check_block(declarations),
+            Statement::Return { value: Some(value), .. } => self.check_expr(value),
+            Statement::Return { value: None, .. } => {},
+            Statement::Break { span } | Statement::Continue { span } => {
+                if !self.in_loop {
+                    let keyword = if matches!(stmt, Statement::Break { .. }) { "break" } else { "continue" };
+                    self.errors.push(SemanticError::NotInLoop { keyword, span: *span });
+                }
+            },
+            Statement::ExprStmt { expr } => self.check_expr(expr),
+            Statement::FuncDef { func, .. } => self.check_lambda(func),
+            Statement::If { condition, then_block, else_block } => {
+                self.check_expr(condition);
+                self.check_scoped_block(then_block);
+                if let Some(else_block) = else_block {
+                    self.check_scoped_block(else_block);
+                }
+            },
+            Statement::While { condition, body } => {
+                self.check_expr(condition);
+                self.with_in_loop(true, |checker| checker.check_scoped_block(body));
+            },
+            Statement::For { init, condition, step, body } => {
+                self.with_scope(|checker| {
+                    checker.check_statement(init);
+                    checker.check_expr(condition);
+                    // the step runs after the body, but can't see the body's variables
+                    checker.check_statement(step);
+                    checker.with_in_loop(true, |checker| checker.check_scoped_block(body));
+                });
+            },
+            Statement::ForEach { variable, span, iterable, body } => {
+                self.check_expr(iterable);
+                self.with_scope(|checker| {
+                    // looping just to repeat the body a number of times is fine, so an unused
+                    // loop variable isn't warned about
+                    checker.declare(variable.name(), Some(*span), false);
+                    checker.with_in_loop(true, |checker| checker.check_scoped_block(body));
+                });
+            },
+        }
+    }
+
+    fn check_expr(&mut self, expr: &'p Expr<'a>) {
+        match expr {
+            Expr::IntLit { .. }
+                | Expr::FloatLit { .. }
+                | Expr::BoolLit { .. }
+                | Expr::StringLit { .. } => {},
+            // the resolver only resolves a name used as a value to a function if the function exists
+            Expr::VarRef { slot: Slot::Function, .. } => {},
+            Expr::VarRef { variable, span, .. } => {
+                if let Some(decl) = self.find_declaration(variable.name(), *span) {
+                    decl.read = true;
+                }
+            },
+            Expr::Add { .. }
+                | Expr::Sub { .. }
+                | Expr::Mul { .. }
+                | Expr::Div { .. }
+                | Expr::Mod { .. }
+                | Expr::Pow { .. }
+                | Expr::Eq { .. }
+                | Expr::NotEq { .. }
+                | Expr::Less { .. }
+                | Expr::Greater { .. }
+                | Expr::LessEq { .. }
+                | Expr::GreaterEq { .. }
+                | Expr::And { .. }
+                | Expr::Or { .. }
+                | Expr::Range { .. }
+                | Expr::RangeInclusive { .. } => {
+                // walk the chain down the lhs, like `Context::reduce_binop_chain`
+                let mut rhss = Vec::new();
+                let mut leftmost = expr;
+                while let Some((lhs, _, rhs)) = ast::split_binop(leftmost) {
+                    rhss.push(rhs);
+                    leftmost = lhs;
+                }
+                self.check_expr(leftmost);
+                for rhs in rhss.into_iter().rev() {
+                    self.check_expr(rhs);
+                }
+            },
+            Expr::Neg { operand } | Expr::Not { operand } => self.check_expr(operand),
+            Expr::Assign { target, op, value } => {
+                let (_, _, indexes) = ast::split_assign_target(target);
+                for index in indexes {
+                    self.check_expr(index);
+                }
+                self.check_expr(value);
+                // assigning to a variable doesn't count as reading it, but a
+                // compound assignment reads it too
+                let mut root = &**target;
+                while let Expr::Index { array, .. } = root {
+                    root = array;
+                }
+                if let Expr::VarRef { variable, span, .. } = root {
+                    if let Some(decl) = self.find_declaration(variable.name(), *span) {
+                        decl.read |= op.is_some();
+                    }
+                }
+            },
+            Expr::Block { body, value } => {
+                self.with_scope(|checker| {
+                    checker.with_in_loop(false, |checker| checker.check_block(body));
+                    if let Some(value) = value {
+                        checker.check_expr(value);
+                    }
+                });
+            },
+            Expr::InterpolatedString { parts } => {
+                for part in parts {
+                    if let StringPart::Expr(expr) = part {
+                        self.check_expr(expr);
+                    }
+                }
+            },
+            Expr::ArrayLit { elements } => {
+                for elem in elements {
+                    self.check_expr(elem);
+                }
+            },
+            Expr::MapLit { entries } => {
+                for (key, value) in entries {
+                    self.check_expr(key);
+                    self.check_expr(value);
+                }
+            },
+            Expr::Index { array, index } => {
+                self.check_expr(array);
+                self.check_expr(index);
+            },
+            Expr::IfExpr { condition, then_expr, else_expr } => {
+                self.check_expr(condition);
+                self.check_expr(then_expr);
+                self.check_expr(else_expr);
+            },
+            Expr::Lambda(lambda) => self.check_lambda(lambda),
+            Expr::FuncCall { callee, args, span } => {
+                // only calls by name can be checked, since a function value
+                // could be any function
+                match &**callee {
+                    Expr::VarRef { variable, slot: Slot::Function, .. } => match self.program.global.arity(variable) {
+                        None => self.errors.push(SemanticError::UndefinedFunction {
+                            name: variable.to_string(),
+                            span: *span,
+                        }),
+                        Some(expected) if expected != args.len() => self.errors.push(SemanticError::ArityMismatch {
+                            func: variable.to_string(),
+                            expected,
+                            got: args.len(),
+                            span: *span,
+                        }),
+                        Some(_) => {},
+                    },
+                    callee => self.check_expr(callee),
+                }
+                for arg in args {
+                    self.check_expr(arg);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast, lex};
+
+    fn checked(src: &str, f: impl FnOnce(&Program)) {
+        let tokens = lex::lex_tokens(src).collect::<Result<Vec<_>, _>>().unwrap();
+        let items = ast::parse_items(tokens.into_iter()).collect::<Result<Vec<_>, _>>().unwrap();
+        f(&Program::from_items(items.into_iter()).unwrap());
+    }
+
+    /// The names in the errors the checker finds in `src`
+    fn error_names(src: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        checked(src, |program| {
+            for e in check_program(program).err().unwrap_or_default() {
+                match e {
+                    SemanticError::UndefinedVariable { name, .. } => names.push(format!("undefined {name}")),
+                    SemanticError::Redeclaration { name, .. } => names.push(format!("redeclared {name}")),
+                    other => names.push(other.to_string()),
+                }
+            }
+        });
+        names
+    }
+
+    #[test]
+    fn assigning_to_an_undeclared_variable() {
+        assert_eq!(error_names("begin { y = 1; var b = 0; b = y; }"), ["undefined y", "undefined y"]);
+        assert_eq!(error_names("begin { b[0] = 1; c[0][1] += 2; }"), ["undefined b", "undefined c"]);
+        assert!(error_names("var g = 0; begin { var a = [0]; a[0] = 1; g = 2; }").is_empty());
+    }
+
+    #[test]
+    fn assigning_doesnt_count_as_reading() {
+        checked("begin { var x = 1; x = 2; }", |program| {
+            assert!(matches!(&lint_program(program)[..], [Warning::UnusedVariable { name, .. }] if name == "x"));
+        });
+    }
+
+    #[test]
+    fn redeclaring_in_the_same_scope() {
+        assert_eq!(error_names("begin { var a = 1; var a = 2; print(a); }"), ["redeclared a"]);
+        assert_eq!(error_names("func f(a) { var a = 1; return a; } begin { f(1); }"), ["redeclared a"]);
+        assert_eq!(error_names("begin { func h() { } func h() { } h(); }"), ["redeclared h"]);
+        assert_eq!(error_names("var g = 1; var g = 2; begin { }"), ["redeclared g"]);
+    }
+
+    #[test]
+    fn shadowing_in_an_inner_scope_isnt_a_redeclaration() {
+        assert!(error_names("begin { var a = 1; if true { var a = 2; print(a); } for a in [a] { var a = 3; print(a); } }").is_empty());
+    }
+
+    #[test]
+    fn calls_are_checked_against_every_function() {
+        assert_eq!(error_names("begin { if false { nope(); } }"), ["no definition for func nope (called at byte 19)"]);
+        assert_eq!(
+            error_names("func f(a, b) { return a; } begin { f(1); len(1, 2); }"),
+            [
+                "func f takes 2 args, but is called with 1 at byte 35",
+                "func len takes 1 args, but is called with 2 at byte 41",
+            ],
+        );
+        assert!(error_names("begin { later(1); } func later(x) { return x; }").is_empty());
+    }
+
+    #[test]
+    fn reading_an_undeclared_variable() {
+        assert_eq!(error_names("begin { var x = 1; print(y); }"), ["undefined y"]);
+        // a variable is only in scope in its block, and after its declaration
+        assert_eq!(error_names("begin { if true { var t = 1; } print(t); }"), ["undefined t"]);
+        assert_eq!(error_names("begin { print(a); var a = 1; }"), ["undefined a"]);
+        assert!(error_names("var g = 1; func f() { return g; } begin { print(f()); }").is_empty());
+    }
+
+    #[test]
+    fn break_outside_a_loop() {
+        assert_eq!(error_names("begin { break; }"), ["break at byte 8 isn't in a loop"]);
+        assert_eq!(
+            error_names("begin { while true { var f = func() { continue; }; break; } }"),
+            ["continue at byte 38 isn't in a loop"],
+        );
+    }
+
+    #[test]
+    fn errors_are_in_source_order() {
+        let src = "func b() { return z; } begin { y(); print(x); } func a() { return w(1); }";
+        for _ in 0..5 {
+            assert_eq!(
+                error_names(src),
+                [
+                    "undefined z",
+                    "no definition for func y (called at byte 31)",
+                    "undefined x",
+                    "no definition for func w (called at byte 66)",
+                ],
+            );
+        }
+    }
+
+    #[test]
+    fn unused_variables_are_warned_about_once() {
+        checked("begin { var used = 1; var unused = 2; print(used); }", |program| {
+            assert!(matches!(&lint_program(program)[..], [Warning::UnusedVariable { name, .. }] if name == "unused"));
+        });
+        checked("func f(arg) { return 0; } begin { for i in [1] { } print(f(1)); }", |program| {
+            assert!(lint_program(program).is_empty());
+        });
+    }
+}
@@ -0,0 +1,559 @@
+//! Lowering a program to a stack-based bytecode, which `vm` runs as an
+//! alternative to walking the AST.
+//!
+//! Each op pops its operands off the VM's value stack and pushes its result.
+//! Every expression leaves exactly one value on the stack, and every statement
+//! leaves the stack how it found it. Locals use the slots from the `resolve`
+//! pass, so a function's frame is laid out the same as in the interpreter.
+//!
+//! Only part of the language compiles so far: arrays, maps, ranges, indexing, string interpolation,
+//! block expressions, function values, lambdas and `for x in` loops fail with
+//! `CompileError::Unsupported`.
+
+use crate::ast::{self, Expr, Ident, Item, Slot, Statement, Type};
+use crate::interp::{Builtin, IdentMap, Program, ProgramError, Value};
+use crate::lex::{Span, Token};
+
+#[derive(Debug)]
+pub(crate) enum Op {
+    Push(Value),
+    /// Discard the top value
+    Pop,
+
+    LoadLocal(usize),
+    /// Pop a value into a new local at the end of the frame, named `local_names[name]`
+    DeclareLocal {
+        name: usize,
+        is_const: bool,
+    },
+    StoreLocal(usize),
+    /// Fail with a redeclaration of `local_names[name]`
+    Redeclaration {
+        name: usize,
+    },
+    /// Drop the locals of a scope that's ending, so the frame has `len` left
+    EndScope {
+        len: usize,
+    },
+
+    LoadGlobal(usize),
+    /// Pop the initial value of a global, failing if it's already been initialized
+    DefineGlobal {
+        global: usize,
+        is_const: bool,
+    },
+    StoreGlobal(usize),
+
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eq,
+    NotEq,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Neg,
+    Not,
+
+    Jump(usize),
+    /// Pop a bool and jump if it's false. `what` describes the bool for the error if it isn't one.
+    JumpIfFalse {
+        target: usize,
+        what: &'static str,
+    },
+    /// Pop a bool and jump if it's true
+    JumpIfTrue {
+        target: usize,
+        what: &'static str,
+    },
+
+    /// Call `functions[func]` with the top `argc` values as args
+    Call {
+        func: usize,
+        argc: usize,
+    },
+    CallBuiltin {
+        builtin: Builtin,
+        argc: usize,
+    },
+    /// Fail, since there's no function by this name. Its args are still
+    /// evaluated first, for their side effects.
+    CallUndefined {
+        name: String,
+        span: Span,
+    },
+    /// Run the current function again with the top `argc` values as its args
+    TailCall {
+        argc: usize,
+    },
+    /// Return the top value from the current function
+    Return,
+    /// Fail, since a `break` or `continue` isn't in a loop
+    NotInLoop {
+        keyword: &'static str,
+    },
+    /// The end of the begin block
+    Halt,
+}
+
+#[derive(Debug)]
+pub(crate) struct CompiledFunction {
+    pub(crate) name: String,
+    /// Indices into `Bytecode::local_names`
+    pub(crate) arg_names: Vec<usize>,
+    pub(crate) arg_types: Vec<Option<Type>>,
+    pub(crate) return_type: Option<Type>,
+    pub(crate) code: Vec<Op>,
+}
+
+/// A compiled program, ready for `vm::Vm` to run
+#[derive(Debug)]
+pub struct Bytecode {
+    pub(crate) functions: Vec<CompiledFunction>,
+    /// Initializes the globals, then runs the begin block
+    pub(crate) begin: Vec<Op>,
+    pub(crate) global_names: Vec<String>,
+    /// Names of locals, for error messages and for reading the begin block's variables at the end
+    pub(crate) local_names: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Program(ProgramError),
+    /// Something the bytecode compiler can't lower yet
+    Unsupported {
+        /// What it is, like "array literals"
+        what: &'static str,
+    },
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Program(e) => write!(f, "{e}"),
+            Self::Unsupported { what } => write!(f, "{what} aren't supported by the bytecode compiler yet"),
+        }
+    }
+}
+
+/// Lower a whole program to bytecode
+pub fn compile<'a>(items: impl Iterator<Item=Item<'a>>) -> Result<Bytecode, CompileError> {
+    let program = Program::from_items(items).map_err(CompileError::Program)?;
+
+    let mut names = Names::default();
+    // stable indices for the functions, which are kept in a map
+    let funcs: Vec<_> = program.global.functions.values().collect();
+    for (i, func) in funcs.iter().enumerate() {
+        names.functions.insert(func.name.clone(), i);
+    }
+
+    let mut functions = Vec::new();
+    for func in funcs {
+        let mut compiler = Compiler::new(&mut names, func.arg_names.len());
+        compiler.compile_block(&func.body)?;
+        compiler.code.extend([Op::Push(Value::Unit), Op::Return]);
+        functions.push(CompiledFunction {
+            name: func.name.to_string(),
+            arg_names: func.arg_names.iter().map(|name| compiler.names.local(name)).collect(),
+            arg_types: func.arg_types.clone(),
+            return_type: func.return_type,
+            code: compiler.code,
+        });
+    }
+
+    let mut compiler = Compiler::new(&mut names, 0);
+    for global in &program.global_vars {
+        compiler.compile_expr(&global.value)?;
+        let global_index = compiler.names.global(&global.name);
+        compiler.code.push(Op::DefineGlobal { global: global_index, is_const: global.is_const });
+    }
+    compiler.compile_block(&program.begin_body)?;
+    compiler.code.push(Op::Halt);
+    let begin = compiler.code;
+
+    Ok(Bytecode {
+        functions,
+        begin,
+        global_names: names.global_names,
+        local_names: names.local_names,
+    })
+}
+
+/// Indices for the names the bytecode refers to, shared by every function
+#[derive(Default)]
+struct Names<'a> {
+    functions: IdentMap<'a, usize>,
+    globals: IdentMap<'a, usize>,
+    global_names: Vec<String>,
+    locals: IdentMap<'a, usize>,
+    local_names: Vec<String>,
+}
+
+impl<'a> Names<'a> {
+    fn global(&mut self, name: &Ident<'a>) -> usize {
+        *self.globals.entry(name.clone()).or_insert_with(|| {
+            self.global_names.push(name.to_string());
+            self.global_names.len() - 1
+        })
+    }
+
+    fn local(&mut self, name: &Ident<'a>) -> usize {
+        *self.locals.entry(name.clone()).or_insert_with(|| {
+            self.local_names.push(name.to_string());
+            self.local_names.len() - 1
+        })
+    }
+}
+
+/// A loop whose body is being compiled
+struct Loop {
+    /// How many locals were in scope where the body starts
+    live_locals: usize,
+    /// The jumps of `break`s, to point past the loop once it's done
+    breaks: Vec<usize>,
+    /// The jumps of `continue`s, to point at the step or condition once it's done
+    continues: Vec<usize>,
+}
+
+/// Compiles the code for one function, or for the begin block
+struct Compiler<'n, 'a> {
+    names: &'n mut Names<'a>,
+    code: Vec<Op>,
+    /// How many locals are in scope, which is how long the frame is at this point
+    live_locals: usize,
+    /// The loops around what's being compiled, innermost last
+    loops: Vec<Loop>,
+}
+
+impl<'n, 'a> Compiler<'n, 'a> {
+    fn new(names: &'n mut Names<'a>, argc: usize) -> Self {
+        Self { names, code: Vec::new(), live_locals: argc, loops: Vec::new() }
+    }
+
+    /// Emit a jump to be pointed somewhere with `patch_jump` later, returning where it is
+    fn emit_jump(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    /// Point the jump at `at` to the next op emitted
+    fn patch_jump(&mut self, at: usize) {
+        self.patch_jump_to(at, self.code.len());
+    }
+
+    fn patch_jump_to(&mut self, at: usize, to: usize) {
+        match &mut self.code[at] {
+            Op::Jump(target) | Op::JumpIfFalse { target, .. } | Op::JumpIfTrue { target, .. } => *target = to,
+            other => unreachable!("{other:?} isn't a jump"),
+        }
+    }
+
+    /// Compile a loop body. Its `continue`s jump to `continue_target`, or to
+    /// where the body ends if that's `None`. Returns the jumps of its `break`s.
+    fn compile_loop_body(
+        &mut self,
+        body: &[Statement<'a>],
+        continue_target: Option<usize>,
+    ) -> Result<Vec<usize>, CompileError> {
+        self.loops.push(Loop { live_locals: self.live_locals, breaks: Vec::new(), continues: Vec::new() });
+        let res = self.compile_scoped_block(body);
+        let Loop { breaks, continues, .. } = self.loops.pop().unwrap();
+        res?;
+        let continue_target = continue_target.unwrap_or(self.code.len());
+        for jump in continues {
+            self.patch_jump_to(jump, continue_target);
+        }
+        Ok(breaks)
+    }
+
+    /// Compile a `break`, or a `continue` if `is_continue`
+    fn compile_loop_jump(&mut self, is_continue: bool) {
+        let Some(innermost) = self.loops.last() else {
+            let keyword = if is_continue { "continue" } else { "break" };
+            self.code.push(Op::NotInLoop { keyword });
+            return;
+        };
+        // leave the scopes of the body, which the jump skips the ends of
+        if self.live_locals != innermost.live_locals {
+            self.code.push(Op::EndScope { len: innermost.live_locals });
+        }
+        let jump = self.emit_jump(Op::Jump(0));
+        let innermost = self.loops.last_mut().unwrap();
+        if is_continue {
+            innermost.continues.push(jump);
+        } else {
+            innermost.breaks.push(jump);
+        }
+    }
+
+    fn with_scope(&mut self, f: impl FnOnce(&mut Self) -> Result<(), CompileError>) -> Result<(), CompileError> {
+        let scope_start = self.live_locals;
+        f(self)?;
+        if self.live_locals != scope_start {
+            self.code.push(Op::EndScope { len: scope_start });
+            self.live_locals = scope_start;
+        }
+        Ok(())
+    }
+
+    fn compile_block(&mut self, stmts: &[Statement<'a>]) -> Result<(), CompileError> {
+        // they're declared before the rest of their block, so check before compiling any of it
+        if stmts.iter().any(|stmt| matches!(stmt, Statement::FuncDef { .. })) {
+            return Err(CompileError::Unsupported { what: "functions defined in blocks" });
+        }
+        stmts.iter().try_for_each(|stmt| self.compile_statement(stmt))
+    }
+
+    fn compile_scoped_block(&mut self, stmts: &[Statement<'a>]) -> Result<(), CompileError> {
+        self.with_scope(|compiler| compiler.compile_block(stmts))
+    }
+
+    fn compile_statement(&mut self, stmt: &Statement<'a>) -> Result<(), CompileError> {
+        match stmt {
+            Statement::VarDeclaration { variable, value, is_const, slot, .. } => {
+                let Slot::Local(i) = slot else {
+                    unreachable!("{variable} wasn't resolved to a local");
+                };
+                let name = self.names.local(variable);
+                // the resolver gives a redeclaration the slot of the variable
+                // it redeclares, which is already in scope
+                if *i < self.live_locals {
+                    self.code.push(Op::Redeclaration { name });
+                    return Ok(());
+                }
+                self.compile_expr(value)?;
+                self.code.push(Op::DeclareLocal { name, is_const: *is_const });
+                self.live_locals += 1;
+            },
+            Statement::MultiVarDeclaration { declarations } => self.compile_block(declarations)?,
+            Statement::FuncDef { .. } => unreachable!("compile_block doesn't compile blocks with functions defined in them"),
+            Statement::Return { value, is_tail_call } => match value {
+                Some(Expr::FuncCall { args, .. }) if *is_tail_call => {
+                    for arg in args {
+                        self.compile_expr(arg)?;
+                    }
+                    self.code.push(Op::TailCall { argc: args.len() });
+                },
+                Some(value) => {
+                    self.compile_expr(value)?;
+                    self.code.push(Op::Return);
+                },
+                None => {
+                    self.code.push(Op::Push(Value::Unit));
+                    self.code.push(Op::Return);
+                },
+            },
+            Statement::Break { .. } => self.compile_loop_jump(false),
+            Statement::Continue { .. } => self.compile_loop_jump(true),
+            Statement::ExprStmt { expr } => {
+                self.compile_expr(expr)?;
+                self.code.push(Op::Pop);
+            },
+            Statement::If { condition, then_block, else_block } => {
+                self.compile_expr(condition)?;
+                let to_else = self.emit_jump(Op::JumpIfFalse { target: 0, what: "condition" });
+                self.compile_scoped_block(then_block)?;
+                match else_block {
+                    Some(else_block) => {
+                        let to_end = self.emit_jump(Op::Jump(0));
+                        self.patch_jump(to_else);
+                        self.compile_scoped_block(else_block)?;
+                        self.patch_jump(to_end);
+                    },
+                    None => self.patch_jump(to_else),
+                }
+            },
+            Statement::While { condition, body } => {
+                let start = self.code.len();
+                self.compile_expr(condition)?;
+                let to_end = self.emit_jump(Op::JumpIfFalse { target: 0, what: "condition" });
+                let breaks = self.compile_loop_body(body, Some(start))?;
+                self.code.push(Op::Jump(start));
+                self.patch_jump(to_end);
+                for jump in breaks {
+                    self.patch_jump(jump);
+                }
+            },
+            Statement::For { init, condition, step, body } => {
+                self.with_scope(|compiler| {
+                    compiler.compile_statement(init)?;
+                    let start = compiler.code.len();
+                    compiler.compile_expr(condition)?;
+                    let to_end = compiler.emit_jump(Op::JumpIfFalse { target: 0, what: "condition" });
+                    // continuing goes on to the step, which is right after the body
+                    let breaks = compiler.compile_loop_body(body, None)?;
+                    compiler.compile_statement(step)?;
+                    compiler.code.push(Op::Jump(start));
+                    compiler.patch_jump(to_end);
+                    for jump in breaks {
+                        compiler.patch_jump(jump);
+                    }
+                    Ok(())
+                })?;
+            },
+            Statement::ForEach { .. } => return Err(CompileError::Unsupported { what: "for-in loops" }),
+        }
+
+        Ok(())
+    }
+
+    fn compile_expr(&mut self, expr: &Expr<'a>) -> Result<(), CompileError> {
+        // walk chains down the lhs, like `Context::reduce_binop_chain`. && and ||
+        // jump over their rhs, so they're compiled below, as are ranges.
+        let mut rhss = Vec::new();
+        let mut leftmost = expr;
+        while let Some((lhs, op, rhs)) = ast::split_binop(leftmost) {
+            if matches!(op, Token::AndAnd | Token::OrOr | Token::DotDot | Token::DotDotEquals) {
+                break;
+            }
+            rhss.push((op, rhs));
+            leftmost = lhs;
+        }
+        if !rhss.is_empty() {
+            self.compile_expr(leftmost)?;
+            for (op, rhs) in rhss.into_iter().rev() {
+                self.compile_expr(rhs)?;
+                self.code.push(binop(&op));
+            }
+            return Ok(());
+        }
+
+        match expr {
+            Expr::IntLit { value } => self.code.push(Op::Push(Value::Int(*value))),
+            Expr::FloatLit { value } => self.code.push(Op::Push(Value::Float(*value))),
+            Expr::BoolLit { value } => self.code.push(Op::Push(Value::Bool(*value))),
+            Expr::StringLit { value } => self.code.push(Op::Push(Value::String(value.as_str().into()))),
+            Expr::VarRef { variable, slot, .. } => {
+                let op = match slot {
+                    Slot::Local(i) => Op::LoadLocal(*i),
+                    Slot::Outer { .. } => unreachable!("{variable} is only in the body of something that isn't compiled"),
+                    Slot::Global => Op::LoadGlobal(self.names.global(variable)),
+                    Slot::Function => return Err(CompileError::Unsupported { what: "function values" }),
+                    Slot::Unresolved => unreachable!("{variable} wasn't resolved"),
+                };
+                self.code.push(op);
+            },
+            Expr::Assign { target, op, value } => {
+                let Expr::VarRef { variable, slot, .. } = &**target else {
+                    return Err(CompileError::Unsupported { what: "index assignments" });
+                };
+                if let Some(op) = op {
+                    self.compile_expr(target)?;
+                    self.compile_expr(value)?;
+                    self.code.push(binop(&op.binop()));
+                } else {
+                    self.compile_expr(value)?;
+                }
+                // store it, then load it back as the value of the assignment
+                let (store, load) = match slot {
+                    Slot::Local(i) => (Op::StoreLocal(*i), Op::LoadLocal(*i)),
+                    Slot::Outer { .. } => unreachable!("{variable} is only in the body of something that isn't compiled"),
+                    Slot::Global => {
+                        let global = self.names.global(variable);
+                        (Op::StoreGlobal(global), Op::LoadGlobal(global))
+                    },
+                    Slot::Function => unreachable!("{variable} was resolved to a function, but is assigned to"),
+                    Slot::Unresolved => unreachable!("{variable} wasn't resolved"),
+                };
+                self.code.push(store);
+                self.code.push(load);
+            },
+            Expr::And { lhs, rhs } => self.compile_short_circuit(lhs, rhs, "operand of &&", false)?,
+            Expr::Or { lhs, rhs } => self.compile_short_circuit(lhs, rhs, "operand of ||", true)?,
+            Expr::Neg { operand } => {
+                self.compile_expr(operand)?;
+                self.code.push(Op::Neg);
+            },
+            Expr::Not { operand } => {
+                self.compile_expr(operand)?;
+                self.code.push(Op::Not);
+            },
+            Expr::IfExpr { condition, then_expr, else_expr } => {
+                self.compile_expr(condition)?;
+                let to_else = self.emit_jump(Op::JumpIfFalse { target: 0, what: "condition" });
+                self.compile_expr(then_expr)?;
+                let to_end = self.emit_jump(Op::Jump(0));
+                self.patch_jump(to_else);
+                self.compile_expr(else_expr)?;
+                self.patch_jump(to_end);
+            },
+            Expr::FuncCall { callee, args, span } => {
+                let Expr::VarRef { variable: ref func_name, slot: Slot::Function, .. } = **callee else {
+                    return Err(CompileError::Unsupported { what: "calls of function values" });
+                };
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                let argc = args.len();
+                let op = match (self.names.functions.get(func_name), Builtin::from_name(func_name.name())) {
+                    (Some(&func), _) => Op::Call { func, argc },
+                    (None, Some(builtin)) => Op::CallBuiltin { builtin, argc },
+                    (None, None) => Op::CallUndefined { name: func_name.to_string(), span: *span },
+                };
+                self.code.push(op);
+            },
+            Expr::InterpolatedString { .. } => return Err(CompileError::Unsupported { what: "interpolated strings" }),
+            Expr::Block { .. } => return Err(CompileError::Unsupported { what: "block expressions" }),
+            Expr::ArrayLit { .. } => return Err(CompileError::Unsupported { what: "array literals" }),
+            Expr::MapLit { .. } => return Err(CompileError::Unsupported { what: "map literals" }),
+            Expr::Index { .. } => return Err(CompileError::Unsupported { what: "index expressions" }),
+            Expr::Range { .. } | Expr::RangeInclusive { .. } => {
+                return Err(CompileError::Unsupported { what: "ranges" });
+            },
+            Expr::Lambda { .. } => return Err(CompileError::Unsupported { what: "lambdas" }),
+            _ => unreachable!("binary operators are handled above"),
+        }
+
+        Ok(())
+    }
+
+    /// Compile `lhs && rhs`, or `lhs || rhs` if `stop_on` is true. Both operands have to be
+    /// bools, and `rhs` is skipped if `lhs` is `stop_on`.
+    fn compile_short_circuit(
+        &mut self,
+        lhs: &Expr<'a>,
+        rhs: &Expr<'a>,
+        what: &'static str,
+        stop_on: bool,
+    ) -> Result<(), CompileError> {
+        let jump = |target| if stop_on {
+            Op::JumpIfTrue { target, what }
+        } else {
+            Op::JumpIfFalse { target, what }
+        };
+
+        self.compile_expr(lhs)?;
+        let lhs_stops = self.emit_jump(jump(0));
+        self.compile_expr(rhs)?;
+        let rhs_stops = self.emit_jump(jump(0));
+        self.code.push(Op::Push(Value::Bool(!stop_on)));
+        let to_end = self.emit_jump(Op::Jump(0));
+        self.patch_jump(lhs_stops);
+        self.patch_jump(rhs_stops);
+        self.code.push(Op::Push(Value::Bool(stop_on)));
+        self.patch_jump(to_end);
+        Ok(())
+    }
+}
+
+fn binop(op: &Token) -> Op {
+    match op {
+        Token::Plus => Op::Add,
+        Token::Minus => Op::Sub,
+        Token::Star => Op::Mul,
+        Token::Slash => Op::Div,
+        Token::Percent => Op::Mod,
+        Token::StarStar => Op::Pow,
+        Token::DoubleEquals => Op::Eq,
+        Token::NotEquals => Op::NotEq,
+        Token::Less => Op::Less,
+        Token::Greater => Op::Greater,
+        Token::LessEquals => Op::LessEq,
+        Token::GreaterEquals => Op::GreaterEq,
+        other => unreachable!("{other:?} isn't a binary operator that compiles to one op"),
+    }
+}
@@ -0,0 +1,420 @@
+use crate::ast::{self, Expr, Ident, Item, Statement, StringPart, Type};
+use crate::lex::Token;
+use std::fmt::{self, Display, Write};
+
+/// Format a parsed program back to source, in a canonical style. Formatting
+/// the result of parsing the output again gives back the same output.
+pub fn format_items(items: &[Item]) -> String {
+    let mut out = String::new();
+
+    for (i, item) in items.iter().enumerate() {
+        // consecutive globals are grouped together, and everything else separated by a blank line
+        let is_global = |item| matches!(item, &Item::GlobalVar { .. });
+        if i != 0 && !(is_global(&items[i - 1]) && is_global(item)) {
+            out.push('\n');
+        }
+
+        let body = match item {
+            Item::GlobalVar { variable, value, is_const, .. } => {
+                let kw = if *is_const { "const" } else { "var" };
+                writeln!(out, "{kw} {variable} = {value};").unwrap();
+                continue;
+            },
+            Item::EntryBlock { body, .. } => {
+                out.push_str("begin ");
+                body
+            },
+            Item::FuncDef { name, arg_names, arg_types, return_type, body, .. } => {
+                write!(out, "func {name}").unwrap();
+                write_signature(&mut out, arg_names, arg_types, *return_type).unwrap();
+                out.push(' ');
+                body
+            },
+        };
+        format_block(&mut out, body, 0);
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Write a function's args in parentheses, and its return type if it has one
+fn write_signature(
+    out: &mut impl Write,
+    arg_names: &[Ident],
+    arg_types: &[Option<Type>],
+    return_type: Option<Type>,
+) -> fmt::Result {
+    out.write_char('(')?;
+    for (i, (arg, ty)) in arg_names.iter().zip(arg_types).enumerate() {
+        if i != 0 {
+            out.write_str(", ")?;
+        }
+        out.write_str(arg.name())?;
+        if let Some(ty) = ty {
+            write!(out, ": {}", ty.name())?;
+        }
+    }
+    out.write_char(')')?;
+    if let Some(ty) = return_type {
+        write!(out, " -> {}", ty.name())?;
+    }
+    Ok(())
+}
+
+/// Write a `{ }`-delimited block, with the braces at `indent` levels of indentation
+fn format_block(out: &mut String, stmts: &[Statement], indent: usize) {
+    if stmts.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+
+    out.push_str("{\n");
+    for stmt in stmts {
+        push_indent(out, indent + 1);
+        format_statement(out, stmt, indent + 1);
+        out.push('\n');
+    }
+    push_indent(out, indent);
+    out.push('}');
+}
+
+fn push_indent(out: &mut String, indent: usize) {
+    for _ in 0..indent {
+        out.push('\t');
+    }
+}
+
+fn format_statement(out: &mut String, stmt: &Statement, indent: usize) {
+    match stmt {
+        Statement::If { .. } => format_if(out, stmt, indent),
+        Statement::While { condition, body } => {
+            write!(out, "while {condition} ").unwrap();
+            format_block(out, body, indent);
+        },
+        Statement::FuncDef { name, func, .. } => {
+            write!(out, "func {name}").unwrap();
+            write_signature(out, &func.arg_names, &func.arg_types, func.return_type).unwrap();
+            out.push(' ');
+            format_block(out, &func.body, indent);
+        },
+        Statement::For { init, condition, step, body } => {
+            out.push_str("for (");
+            format_simple_statement(out, init);
+            write!(out, "; {condition}; ").unwrap();
+            format_simple_statement(out, step);
+            out.push_str(") ");
+            format_block(out, body, indent);
+        },
+        Statement::ForEach { variable, iterable, body, .. } => {
+            write!(out, "for {variable} in {iterable} ").unwrap();
+            format_block(out, body, indent);
+        },
+        _ => {
+            format_simple_statement(out, stmt);
+            out.push(';');
+        },
+    }
+}
+
+fn format_if(out: &mut String, stmt: &Statement, indent: usize) {
+    let Statement::If { condition, then_block, else_block } = stmt else {
+        unreachable!();
+    };
+
+    write!(out, "if {condition} ").unwrap();
+    format_block(out, then_block, indent);
+    match else_block.as_deref() {
+        None => {},
+        Some([elseif @ Statement::If { .. }]) => {
+            out.push_str(" else ");
+            format_if(out, elseif, indent);
+        },
+        Some(else_block) => {
+            out.push_str(" else ");
+            format_block(out, else_block, indent);
+        },
+    }
+}
+
+/// Format a statement that isn't a block statement, without its trailing semicolon
+fn format_simple_statement(out: &mut String, stmt: &Statement) {
+    match stmt {
+        Statement::VarDeclaration { variable, value, is_const, .. } => {
+            let kw = if *is_const { "const" } else { "var" };
+            write!(out, "{kw} {variable} = {value}").unwrap();
+        },
+        Statement::MultiVarDeclaration { declarations } => {
+            for (i, decl) in declarations.iter().enumerate() {
+                let Statement::VarDeclaration { variable, value, is_const, .. } = decl else {
+                    unreachable!("{decl:?} isn't a declaration");
+                };
+                if i == 0 {
+                    out.push_str(if *is_const { "const " } else { "var " });
+                } else {
+                    out.push_str(", ");
+                }
+                write!(out, "{variable} = {value}").unwrap();
+            }
+        },
+        Statement::Return { value: Some(value), .. } => write!(out, "return {value}").unwrap(),
+        Statement::Return { value: None, .. } => out.push_str("return"),
+        Statement::Break { .. } => out.push_str("break"),
+        Statement::Continue { .. } => out.push_str("continue"),
+        Statement::ExprStmt { expr } => write!(out, "{expr}").unwrap(),
+        Statement::If { .. }
+            | Statement::While { .. }
+            | Statement::For { .. }
+            | Statement::ForEach { .. }
+            | Statement::FuncDef { .. } => {
+            unreachable!("{stmt:?} is a block statement");
+        },
+    }
+}
+
+fn binop_symbol(op: &Token) -> &'static str {
+    match op {
+        Token::Plus => "+",
+        Token::Minus => "-",
+        Token::Star => "*",
+        Token::Slash => "/",
+        Token::Percent => "%",
+        Token::StarStar => "**",
+        Token::DoubleEquals => "==",
+        Token::NotEquals => "!=",
+        Token::Less => "<",
+        Token::Greater => ">",
+        Token::LessEquals => "<=",
+        Token::GreaterEquals => ">=",
+        Token::AndAnd => "&&",
+        Token::OrOr => "||",
+        Token::DotDot => "..",
+        Token::DotDotEquals => "..=",
+        other => unreachable!("{other:?} is not a binary operator"),
+    }
+}
+
+/// Binding power of `expr`'s outermost operator, if it's a binary operation
+fn expr_power(expr: &Expr) -> Option<u8> {
+    ast::split_binop(expr).and_then(|(_, op, _)| ast::binop_power(&op))
+}
+
+/// Whether `expr` is written starting with a unary minus
+fn is_negated(expr: &Expr) -> bool {
+    match expr {
+        Expr::Neg { .. } => true,
+        Expr::IntLit { value } => *value < 0,
+        Expr::FloatLit { value } => value.is_sign_negative(),
+        _ => false,
+    }
+}
+
+/// If-expressions and assignments swallow any operators after them, so they need parens as an operand
+fn is_open_ended(expr: &Expr) -> bool {
+    matches!(expr, Expr::IfExpr { .. } | Expr::Assign { .. })
+}
+
+/// Write `expr`, wrapped in parentheses if `needs_parens`
+fn write_operand(f: &mut fmt::Formatter, expr: &Expr, needs_parens: bool) -> fmt::Result {
+    if needs_parens {
+        write!(f, "({expr})")
+    } else {
+        write!(f, "{expr}")
+    }
+}
+
+/// Write a block on one line, since expressions don't know their
+/// indentation, with `value` at the end if it gives a value
+fn write_inline_block(f: &mut fmt::Formatter, body: &[Statement], value: Option<&Expr>) -> fmt::Result {
+    f.write_char('{')?;
+    for stmt in body {
+        let mut out = String::new();
+        format_statement(&mut out, stmt, 0);
+        write!(f, " {out}")?;
+    }
+    if let Some(value) = value {
+        write!(f, " {value}")?;
+    }
+    if !body.is_empty() || value.is_some() {
+        f.write_char(' ')?;
+    }
+    f.write_char('}')
+}
+
+fn write_comma_separated(f: &mut fmt::Formatter, exprs: &[Expr]) -> fmt::Result {
+    for (i, expr) in exprs.iter().enumerate() {
+        if i != 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{expr}")?;
+    }
+    Ok(())
+}
+
+/// Write the contents of a string literal with `s` as its value, without the quotes
+pub(crate) fn write_escaped(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    let mut chars = s.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\\' => f.write_str("\\\\")?,
+            '"' => f.write_str("\\\"")?,
+            '$' if chars.peek() == Some(&'{') => f.write_str("\\$")?,
+            ch => f.write_char(ch)?,
+        }
+    }
+    Ok(())
+}
+
+impl Display for ast::Ident<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Formats an expression with only the parentheses needed to parse back the same way
+impl Display for Expr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((lhs, op, rhs)) = ast::split_binop(self) {
+            let power = ast::binop_power(&op).unwrap();
+            let right_assoc = ast::is_right_associative(&op);
+            // an equal-power operand on the side the operator doesn't group
+            // towards needs parens, and comparisons don't chain, so they need
+            // parens on both sides
+            let lhs_parens = expr_power(lhs)
+                .is_some_and(|p| p < power || (p == power && (right_assoc || power == ast::COMPARISON_POWER)));
+            let rhs_parens = expr_power(rhs).is_some_and(|p| p < power || (p == power && !right_assoc));
+            // `**` binds tighter than unary minus, so a negative base needs parens
+            let lhs_parens = lhs_parens || (power == ast::POW_POWER && is_negated(lhs));
+            write_operand(f, lhs, lhs_parens || is_open_ended(lhs))?;
+            if matches!(op, Token::DotDot | Token::DotDotEquals) {
+                f.write_str(binop_symbol(&op))?;
+            } else {
+                write!(f, " {} ", binop_symbol(&op))?;
+            }
+            return write_operand(f, rhs, rhs_parens || is_open_ended(rhs));
+        }
+
+        match self {
+            Expr::IntLit { value } => write!(f, "{value}"),
+            Expr::FloatLit { value } => {
+                let s = value.to_string();
+                if s.contains('.') {
+                    write!(f, "{s}")
+                } else {
+                    write!(f, "{s}.0")
+                }
+            },
+            Expr::BoolLit { value } => write!(f, "{value}"),
+            Expr::StringLit { value } => {
+                f.write_char('"')?;
+                write_escaped(f, value)?;
+                f.write_char('"')
+            },
+            Expr::InterpolatedString { parts } => {
+                f.write_char('"')?;
+                for part in parts {
+                    match part {
+                        StringPart::Text(text) => write_escaped(f, text)?,
+                        StringPart::Expr(expr) => write!(f, "${{{expr}}}")?,
+                    }
+                }
+                f.write_char('"')
+            },
+            Expr::VarRef { variable, .. } => write!(f, "{variable}"),
+            Expr::Neg { operand } => {
+                f.write_char('-')?;
+                let parens = expr_power(operand).is_some_and(|p| p < ast::POW_POWER);
+                write_operand(f, operand, parens || is_open_ended(operand))
+            },
+            Expr::Not { operand } => {
+                f.write_char('!')?;
+                write_operand(f, operand, expr_power(operand).is_some() || is_open_ended(operand))
+            },
+            Expr::Block { body, value } => write_inline_block(f, body, value.as_deref()),
+            Expr::Lambda(lambda) => {
+                f.write_str("func")?;
+                write_signature(f, &lambda.arg_names, &lambda.arg_types, lambda.return_type)?;
+                f.write_char(' ')?;
+                write_inline_block(f, &lambda.body, None)
+            },
+            Expr::ArrayLit { elements } => {
+                f.write_char('[')?;
+                write_comma_separated(f, elements)?;
+                f.write_char(']')
+            },
+            Expr::MapLit { entries } => {
+                f.write_str("#{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{key}: {value}")?;
+                }
+                f.write_char('}')
+            },
+            Expr::Index { array, index } => {
+                let parens = expr_power(array).is_some()
+                    || is_open_ended(array)
+                    || matches!(**array, Expr::Neg { .. } | Expr::Not { .. });
+                write_operand(f, array, parens)?;
+                write!(f, "[{index}]")
+            },
+            Expr::IfExpr { condition, then_expr, else_expr } => {
+                write!(f, "if {condition} then {then_expr} else {else_expr}")
+            },
+            Expr::Assign { target, op: None, value } => write!(f, "{target} = {value}"),
+            Expr::Assign { target, op: Some(op), value } => write!(f, "{target} {}= {value}", op.binop()),
+            Expr::FuncCall { callee, args, .. } => {
+                let parens = expr_power(callee).is_some()
+                    || is_open_ended(callee)
+                    || matches!(**callee, Expr::Neg { .. } | Expr::Not { .. });
+                write_operand(f, callee, parens)?;
+                f.write_char('(')?;
+                write_comma_separated(f, args)?;
+                f.write_char(')')
+            },
+            _ => unreachable!("binary operators are handled above"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex;
+
+    fn format_source(src: &str) -> String {
+        let tokens = lex::lex_tokens(src).collect::<Result<Vec<_>, _>>().unwrap();
+        let items = ast::parse_items(tokens.into_iter()).collect::<Result<Vec<_>, _>>().unwrap();
+        format_items(&items)
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let formatted = format_source(include_str!("../example.foo"));
+        assert_eq!(format_source(&formatted), formatted);
+
+        let src = "var g=1;const h=2;func f(a:int,b)->int{if a<b{return -a;}else{while true{break;}}return a**b;}\
+            begin{var s=\"x${g+1}\\n\";for(var i=0;i<3;i+=1){s+=\"!\";}for x in 0..=2{continue;}\
+            var l=func(y){return y;};var m=#{\"k\":[1,2.5]};m[\"k\"][0]=(1+2)*3;}";
+        let formatted = format_source(src);
+        assert_eq!(format_source(&formatted), formatted);
+    }
+
+    #[test]
+    fn parentheses_are_kept_only_where_needed() {
+        let formatted = format_source("begin { var a = (1 + 2) * 3; var b = 1 + (2 * 3); var c = 1 - (2 - 3); var d = (1 - 2) - 3; }");
+        assert!(formatted.contains("var a = (1 + 2) * 3;"), "{formatted}");
+        assert!(formatted.contains("var b = 1 + 2 * 3;"), "{formatted}");
+        assert!(formatted.contains("var c = 1 - (2 - 3);"), "{formatted}");
+        assert!(formatted.contains("var d = 1 - 2 - 3;"), "{formatted}");
+
+        let formatted = format_source("begin { var a = (-2) ** 2; var b = -(2 ** 2); var c = (-x) ** 2; var d = -(x * 2); }");
+        assert!(formatted.contains("var a = (-2) ** 2;"), "{formatted}");
+        assert!(formatted.contains("var b = -2 ** 2;"), "{formatted}");
+        assert!(formatted.contains("var c = (-x) ** 2;"), "{formatted}");
+        assert!(formatted.contains("var d = -(x * 2);"), "{formatted}");
+    }
+}
@@ -1,204 +1,1700 @@
-use crate::ast::{Expr, Statement, Item};
-use std::collections::HashMap;
+use crate::ast::{self, Expr, Ident, Lambda, Slot, Statement, StringPart, Item, Type, ReplInput};
+use crate::resolve::{GlobalNames, Resolver};
+use crate::lex::{Span, Token};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
 
+/// How deeply calls to user functions can nest before failing with
+/// `RuntimeError::StackOverflow`. Calls move onto a new stack when they need
+/// to, so reaching this doesn't depend on how big the thread's stack is.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// How close to the end of the stack a call can start before it moves to a new
+/// stack of `CALL_STACK_SIZE`. Calls are the only recursion in the interpreter
+/// that the parser's nesting limit doesn't bound, so this only has to fit
+/// evaluating one function body as deeply nested as the parser allows.
+const CALL_RED_ZONE: usize = 1024 * 1024;
+const CALL_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// How many ints a range can have when it's used as a value, and so built
+/// into an array, before failing with `RuntimeError::RangeTooLong`. A for
+/// loop over a range doesn't build one, so it can be any length.
+pub const MAX_RANGE_LEN: usize = 10_000_000;
+
+/// What lambdas are called in error messages, since they don't have names
+const LAMBDA_NAME: &str = "<lambda>";
+
+/// Something wrong with the overall structure of a program
 #[derive(Debug)]
-struct Context<'a> {
-    vars: HashMap<&'a str, Value>,
-    func_ret: Option<Value>,
+pub enum ProgramError {
+    MissingBegin,
+    /// More than one begin block, with the spans of the first two `begin` keywords
+    MultipleBegin {
+        first_span: Span,
+        second_span: Span,
+    },
+    /// Two functions with the same name, with the spans of both names
+    DuplicateFunction {
+        name: String,
+        first_span: Span,
+        second_span: Span,
+    },
+    /// A function with the same name as a builtin
+    RedefinedBuiltin {
+        name: String,
+        span: Span,
+    },
+}
+
+impl ProgramError {
+    /// Where in the source the error is, if anywhere
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::MissingBegin => None,
+            Self::MultipleBegin { second_span, .. } => Some(*second_span),
+            Self::DuplicateFunction { second_span, .. } => Some(*second_span),
+            Self::RedefinedBuiltin { span, .. } => Some(*span),
+        }
+    }
+}
+
+impl std::fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::MissingBegin => write!(f, "program has no begin block"),
+            Self::MultipleBegin { first_span, second_span } => write!(
+                f,
+                "program has more than one begin block, at byte {} and at byte {}",
+                first_span.start,
+                second_span.start,
+            ),
+            Self::DuplicateFunction { name, first_span, second_span } => write!(
+                f,
+                "func {name} is defined twice, at byte {} and at byte {}",
+                first_span.start,
+                second_span.start,
+            ),
+            Self::RedefinedBuiltin { name, span } => {
+                write!(f, "func {name} at byte {} has the same name as a builtin", span.start)
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    UndefinedVariable(String),
+    UndefinedFunction {
+        name: String,
+        /// Where it was called
+        span: Span,
+    },
+    /// A variable was declared twice in the same scope
+    Redeclaration(String),
+    /// Tried to reassign a variable declared with `const`
+    AssignToConst(String),
+    /// Tried to assign to a closure's copy of a local. See `Expr::Lambda`.
+    AssignToCaptured(String),
+    /// An operation was given a value of the wrong type. Holds a description of the problem.
+    TypeError(String),
+    DivisionByZero,
+    /// A function was called with the wrong number of args
+    ArityMismatch {
+        func: String,
+        expected: usize,
+        got: usize,
+    },
+    /// A `return` was executed in the begin block
+    ReturnOutsideFunction,
+    /// Calls nested deeper than `MAX_CALL_DEPTH`
+    StackOverflow,
+    /// A range used as a value has more than `MAX_RANGE_LEN` ints in it
+    RangeTooLong,
+    /// A `return` ran after the function had already returned
+    ReturnedTwice,
+    IndexOutOfBounds {
+        index: i64,
+        len: usize,
+    },
+    /// A map was indexed by a key it doesn't have
+    MissingKey(String),
+    /// A `return` ran inside a block expression
+    ReturnInBlockExpression,
+    /// A `break` or `continue` ran outside of any loop body. See `Statement::Break`
+    /// for what counts as in one.
+    NotInLoop {
+        keyword: &'static str,
+    },
+    /// The result of an int operation doesn't fit in an int
+    IntegerOverflow {
+        /// The operator, like `+`
+        op: &'static str,
+    },
+    /// An int was raised to a negative int power
+    NegativeExponent,
+    /// `to_int` or `to_float` was given a string that isn't a number of that type
+    ParseValueError {
+        text: String,
+        ty: Type,
+    },
+    /// Tried to call a value that isn't a function
+    NotCallable {
+        ty: Type,
+        /// Where it was called
+        span: Span,
+    },
+    /// `print` couldn't write to the program's output
+    Output(io::Error),
+}
+
+impl RuntimeError {
+    /// Where in the source the error is, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::UndefinedFunction { span, .. } | Self::NotCallable { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UndefinedVariable(name) => write!(f, "variable {name} is not defined"),
+            Self::UndefinedFunction { name, span } => {
+                write!(f, "no definition for func {name} (called at byte {})", span.start)
+            },
+            Self::Redeclaration(name) => write!(f, "redeclaration of variable {name}"),
+            Self::AssignToConst(name) => write!(f, "can't assign to const {name}"),
+            Self::AssignToCaptured(name) => write!(f, "can't assign to captured variable {name}"),
+            Self::TypeError(msg) => write!(f, "type mismatch: {msg}"),
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::ArityMismatch { func, expected, got } => {
+                write!(f, "func {func} takes {expected} args, but was called with {got}")
+            },
+            Self::ReturnOutsideFunction => write!(f, "can't return from begin block"),
+            Self::StackOverflow => write!(f, "stack overflow: calls nested deeper than {MAX_CALL_DEPTH}"),
+            Self::RangeTooLong => write!(f, "range has more than {MAX_RANGE_LEN} ints, which is too many for an array"),
+            Self::ReturnedTwice => write!(f, "control flow reached a second return after the function already returned"),
+            Self::IndexOutOfBounds { index, len } => {
+                write!(f, "index {index} is out of bounds for an array of length {len}")
+            },
+            Self::MissingKey(key) => write!(f, "map has no key {key:?}"),
+            Self::ReturnInBlockExpression => write!(f, "can't return from inside a block expression"),
+            Self::NotInLoop { keyword } => write!(f, "{keyword} isn't in a loop"),
+            Self::IntegerOverflow { op } => write!(f, "integer overflow in {op}"),
+            Self::NegativeExponent => write!(f, "can't raise an int to a negative int power"),
+            Self::ParseValueError { text, ty } => write!(f, "can't parse {text:?} as {}", ty.name()),
+            Self::NotCallable { ty, span } => write!(f, "can't call {} (called at byte {})", ty.with_article(), span.start),
+            Self::Output(e) => write!(f, "can't write output: {e}"),
+        }
+    }
+}
+
+/// Hashes an `Ident` as just its symbol, which is already a unique small int,
+/// so there's no need for `HashMap`'s default DoS-resistant hashing
+#[derive(Default)]
+pub(crate) struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 << 8) | b as u64;
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.0 = i as u64;
+    }
+}
+
+/// A map keyed by variable or function name
+pub(crate) type IdentMap<'a, V> = HashMap<Ident<'a>, V, BuildHasherDefault<SymbolHasher>>;
+
+pub(crate) type IdentSet<'a> = HashSet<Ident<'a>, BuildHasherDefault<SymbolHasher>>;
+
+#[derive(Debug)]
+struct Variable<'a> {
+    name: Ident<'a>,
+    value: Value,
+    is_const: bool,
+}
+
+/// The frame of a function that the one running is nested in
+#[derive(Debug)]
+enum OuterFrame<'a> {
+    /// The frame of a call still in progress, for a function defined in a block of it
+    Live(Vec<Variable<'a>>),
+    /// A closure's copies of a frame's values
+    Captured(Rc<[Value]>),
+}
+
+/// Why the statements being run have stopped before the end of their block
+#[derive(Debug)]
+enum Unwind {
+    /// A `return`, with the value it gives back from the function it's in
+    Return(Value),
+    /// A tail call of the same function, with the args to call it with again
+    TailCall(Vec<Value>),
+    Break,
+    Continue,
+}
+
+impl Unwind {
+    /// The error for a `break` or `continue` that unwound out of a function,
+    /// lambda, block expression or the begin block, without reaching a loop
+    fn not_in_loop(&self) -> RuntimeError {
+        let keyword = match self {
+            Self::Break => "break",
+            Self::Continue => "continue",
+            Self::Return(_) | Self::TailCall(_) => unreachable!("{self:?} isn't a break or continue"),
+        };
+        RuntimeError::NotInLoop { keyword }
+    }
+}
+
+/// Variable scopes and function-call state for one function invocation, or for the `begin` block
+#[derive(Debug)]
+pub struct Context<'a> {
+    /// The locals in scope, indexed by `Slot::Local`. See the `resolve` module
+    /// for how slots are laid out.
+    frame: Vec<Variable<'a>>,
+    /// The frames of the functions that the one running is nested in, for
+    /// `Slot::Outer`, outermost first. Empty for a top-level function or the begin block.
+    outer: Vec<OuterFrame<'a>>,
+    /// Set by a `return`, `break` or `continue` that has run, until whatever it
+    /// unwinds to handles it
+    unwinding: Option<Unwind>,
     global_context: &'a GlobalContext<'a>,
 }
 
 impl<'a> Context<'a> {
     fn new(global_context: &'a GlobalContext<'a>) -> Self {
         Self {
-            vars: HashMap::new(),
-            func_ret: None,
+            frame: Vec::new(),
+            outer: Vec::new(),
+            unwinding: None,
             global_context,
         }
     }
 
-    fn get_var_mut(&mut self, varname: &str) -> Option<&mut Value> {
-        self.vars.get_mut(varname)
+    /// The value of the variable `varname` resolved to
+    fn read_var(&self, varname: &Ident, slot: Slot) -> Result<Value, RuntimeError> {
+        match slot {
+            Slot::Local(i) => Ok(self.frame[i].value.clone()),
+            Slot::Outer { level, index } => Ok(match &self.outer[level] {
+                OuterFrame::Live(vars) => vars[index].value.clone(),
+                OuterFrame::Captured(values) => values[index].clone(),
+            }),
+            Slot::Global => {
+                self.global_context.globals.borrow().get(varname)
+                    .map(|var| var.value.clone())
+                    .ok_or_else(|| RuntimeError::UndefinedVariable(varname.to_string()))
+            },
+            Slot::Function => Ok(Value::Function(varname.name().into())),
+            Slot::Unresolved => unreachable!("{varname} wasn't resolved before running"),
+        }
     }
 
-    fn get_var(&self, varname: &str) -> Option<&Value> {
-        self.vars.get(varname)
+    /// Run `f` on the value of the variable `varname` resolved to, which has to not be const
+    fn modify_var(
+        &mut self,
+        varname: &Ident<'a>,
+        slot: Slot,
+        f: impl FnOnce(&mut Value) -> Result<(), RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        let mut globals;
+        let var = match slot {
+            Slot::Local(i) => &mut self.frame[i],
+            Slot::Outer { level, index } => match &mut self.outer[level] {
+                OuterFrame::Live(vars) => &mut vars[index],
+                // assigning to a closure's copy would only change the copy
+                OuterFrame::Captured(_) => return Err(RuntimeError::AssignToCaptured(varname.to_string())),
+            },
+            Slot::Global => {
+                globals = self.global_context.globals.borrow_mut();
+                globals.get_mut(varname)
+                    .ok_or_else(|| RuntimeError::UndefinedVariable(varname.to_string()))?
+            },
+            Slot::Function => unreachable!("{varname} was resolved to a function, but is assigned to"),
+            Slot::Unresolved => unreachable!("{varname} wasn't resolved before running"),
+        };
+
+        if var.is_const {
+            return Err(RuntimeError::AssignToConst(varname.to_string()));
+        }
+        f(&mut var.value)
     }
 
-    fn create_var(&mut self, varname: &'a str, val: Value) {
-        self.vars.insert(varname, val);
+    /// Look up `varname`, starting from the innermost scope and falling back to globals
+    pub fn get_var(&self, varname: &Ident) -> Option<Value> {
+        match self.frame.iter().rev().find(|var| var.name == *varname) {
+            Some(var) => Some(var.value.clone()),
+            None => self.global_context.globals.borrow().get(varname).map(|var| var.value.clone()),
+        }
+    }
+
+    /// Take a snapshot of every variable in scope, with inner scopes shadowing outer ones
+    pub fn into_variables(self) -> HashMap<String, Value> {
+        let mut vars = HashMap::new();
+        for (name, var) in self.global_context.globals.borrow().iter() {
+            vars.insert(name.to_string(), var.value.clone());
+        }
+        for var in self.frame {
+            vars.insert(var.name.to_string(), var.value);
+        }
+        vars
+    }
+
+    /// Run a line of REPL input, returning its value if it has one. Its variables
+    /// go in the innermost scope, so they're still there for the next input.
+    pub fn eval_repl_input(&mut self, input: &'a mut ReplInput<'a>) -> Result<Option<Value>, RuntimeError> {
+        let globals = self.global_context.names();
+        let mut resolver = Resolver::new(&globals, self.frame.iter().map(|var| var.name.clone()).collect());
+        resolver.resolve_block(&mut input.body);
+        if let Some(value) = &mut input.value {
+            resolver.resolve_expr(value);
+        }
+        let input: &'a ReplInput = input;
+
+        self.eval_block(&input.body)?;
+        match self.unwinding.take() {
+            Some(Unwind::Return(_) | Unwind::TailCall(_)) => return Err(RuntimeError::ReturnOutsideFunction),
+            Some(unwind) => return Err(unwind.not_in_loop()),
+            None => {},
+        }
+
+        input.value.as_ref().map(|value| self.reduce_expr(value)).transpose()
+    }
+
+    /// Create a variable in the next slot of the frame
+    fn create_var(&mut self, varname: Ident<'a>, value: Value, is_const: bool) {
+        self.frame.push(Variable { name: varname, value, is_const });
+    }
+
+    /// How deeply the function running is nested, which is the `level` its own frame would have as a `Slot::Outer`
+    fn level(&self) -> usize {
+        self.outer.len()
     }
 
-    fn has_var(&self, varname: &str) -> bool {
-        self.get_var(varname).is_some()
+    /// Copies of the values in the frames out to the one at `level`, for a closure to capture
+    fn capture_frames(&self, level: usize) -> Vec<Rc<[Value]>> {
+        let outer = self.outer.iter().take(level + 1).map(|frame| match frame {
+            OuterFrame::Live(vars) => vars.iter().map(|var| var.value.clone()).collect(),
+            OuterFrame::Captured(values) => Rc::clone(values),
+        });
+        let current = (level == self.level()).then(|| self.frame.iter().map(|var| var.value.clone()).collect());
+        outer.chain(current).collect()
     }
 
-    fn reduce_expr(&self, expr: &Expr) -> Value {
-        match expr {
+    /// Call `closure`, a function defined in a block of the frame at `level`,
+    /// which that frame is still running. It runs in a frame nested in that one,
+    /// so any frames nested deeper, like the caller's, are put aside until it returns.
+    fn call_local_func(&mut self, level: usize, closure: &Closure, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let global_context = self.global_context;
+        let lambda = global_context.lambda(closure, &args)?;
+        self.outer.push(OuterFrame::Live(std::mem::take(&mut self.frame)));
+        let hidden = self.outer.split_off(level + 1);
+        let res = global_context.nested_call(|| self.run_lambda(closure.name(), lambda, args));
+        self.outer.extend(hidden);
+        let Some(OuterFrame::Live(frame)) = self.outer.pop() else {
+            unreachable!("the caller's frame was put back on top");
+        };
+        self.frame = frame;
+        res
+    }
+
+    /// Run the body of a lambda or function defined in a block in this context's
+    /// frame, which has to be empty
+    fn run_lambda(&mut self, func_name: &str, lambda: &'a Lambda<'a>, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        self.bind_args(func_name, &lambda.arg_names, &lambda.arg_types, args)?;
+        self.eval_block(&lambda.body)?;
+        let ret = match self.unwinding.take() {
+            Some(Unwind::Return(ret)) => ret,
+            Some(Unwind::TailCall(_)) => unreachable!("the resolver only finds tail calls in top-level functions"),
+            Some(unwind) => return Err(unwind.not_in_loop()),
+            None => Value::Unit,
+        };
+        check_return_type(func_name, lambda.return_type, ret)
+    }
+
+    /// Reduce a binary operation. A chain like `1 + 1 + ... + 1` nests down its
+    /// lhs once per operator, up to `ast::MAX_CHAIN_LENGTH` deep, so this walks
+    /// down to the leftmost operand and applies the operators back up instead of
+    /// recursing into each lhs. The other passes walk chains the same way.
+    fn reduce_binop_chain(&mut self, expr: &'a Expr) -> Result<Value, RuntimeError> {
+        let mut pending = Vec::new();
+        let mut leftmost = expr;
+        while let Some((lhs, op, rhs)) = ast::split_binop(leftmost) {
+            pending.push((op, rhs));
+            leftmost = lhs;
+        }
+
+        let mut acc = self.reduce_expr(leftmost)?;
+        for (op, rhs) in pending.into_iter().rev() {
+            acc = self.apply_binop(&op, acc, rhs)?;
+        }
+        Ok(acc)
+    }
+
+    /// Apply the binary operator `op` to the already reduced `lhs` and to `rhs`
+    fn apply_binop(&mut self, op: &Token, lhs: Value, rhs: &'a Expr) -> Result<Value, RuntimeError> {
+        // rust's && and || short-circuit the same way:
+        match op {
+            Token::AndAnd => {
+                return Ok(Value::Bool(bool_operand(lhs, "operand of &&")? && self.reduce_bool(rhs, "operand of &&")?));
+            },
+            Token::OrOr => {
+                return Ok(Value::Bool(bool_operand(lhs, "operand of ||")? || self.reduce_bool(rhs, "operand of ||")?));
+            },
+            _ => {},
+        }
+
+        let rhs = self.reduce_expr(rhs)?;
+        Ok(match op {
+            Token::Plus => add(lhs, rhs)?,
+            Token::Minus => sub(lhs, rhs)?,
+            Token::Star => mul(lhs, rhs)?,
+            Token::Slash => div(lhs, rhs)?,
+            Token::Percent => rem(lhs, rhs)?,
+            Token::StarStar => pow(lhs, rhs)?,
+            Token::DoubleEquals => Value::Bool(values_equal(&lhs, &rhs)),
+            Token::NotEquals => Value::Bool(!values_equal(&lhs, &rhs)),
+            Token::Less => Value::Bool(compare(lhs, rhs)? == Some(Ordering::Less)),
+            Token::Greater => Value::Bool(compare(lhs, rhs)? == Some(Ordering::Greater)),
+            Token::LessEquals => Value::Bool(matches!(compare(lhs, rhs)?, Some(Ordering::Less | Ordering::Equal))),
+            Token::GreaterEquals => {
+                Value::Bool(matches!(compare(lhs, rhs)?, Some(Ordering::Greater | Ordering::Equal)))
+            },
+            Token::DotDot => range_array(range_bounds(lhs, rhs, false)?)?,
+            Token::DotDotEquals => range_array(range_bounds(lhs, rhs, true)?)?,
+            other => unreachable!("{other:?} is not a binary operator"),
+        })
+    }
+
+    fn reduce_expr(&mut self, expr: &'a Expr) -> Result<Value, RuntimeError> {
+        let val = match expr {
             Expr::IntLit { value } => Value::Int(*value),
-            // TODO: remove need for the clone:
-            Expr::VarRef { variable } => self.get_var(&variable).expect(&format!("No variable {variable}")).clone(),
-            Expr::Add { lhs, rhs } => {
-                match (self.reduce_expr(lhs), self.reduce_expr(rhs)) {
-                    (Value::Int(l), Value::Int(r)) => Value::Int(l + r),
-                    _ => panic!("Can't add non-ints"),
+            Expr::FloatLit { value } => Value::Float(*value),
+            Expr::BoolLit { value } => Value::Bool(*value),
+            Expr::StringLit { value } => Value::String(value.as_str().into()),
+            Expr::InterpolatedString { parts } => {
+                let mut s = String::new();
+                for part in parts {
+                    match part {
+                        StringPart::Text(text) => s.push_str(text),
+                        StringPart::Expr(expr) => s.push_str(&self.reduce_expr(expr)?.to_string()),
+                    }
                 }
+                Value::String(s.into())
             },
-            Expr::FuncCall { func_name, args } => {
-                self.global_context
-                    .call_func(func_name, args.iter().map(|i| self.reduce_expr(i)))
+            Expr::VarRef { variable, slot, .. } => match self.read_var(variable, *slot)? {
+                // a function defined in a block can only run with the frames it's
+                // nested in while they're running, so what's used as a value is a
+                // closure like a lambda would give
+                Value::Closure(closure) if closure.frames == ClosureFrames::Live => {
+                    let level = match slot {
+                        Slot::Outer { level, .. } => *level,
+                        _ => self.level(),
+                    };
+                    Value::Closure(Rc::new(Closure {
+                        lambda: closure.lambda,
+                        name: closure.name.clone(),
+                        frames: ClosureFrames::Captured(self.capture_frames(level)),
+                    }))
+                },
+                val => val,
             },
-        }
+            Expr::Add { .. }
+                | Expr::Sub { .. }
+                | Expr::Mul { .. }
+                | Expr::Div { .. }
+                | Expr::Mod { .. }
+                | Expr::Pow { .. }
+                | Expr::Eq { .. }
+                | Expr::NotEq { .. }
+                | Expr::Less { .. }
+                | Expr::Greater { .. }
+                | Expr::LessEq { .. }
+                | Expr::GreaterEq { .. }
+                | Expr::And { .. }
+                | Expr::Or { .. }
+                | Expr::Range { .. }
+                | Expr::RangeInclusive { .. } => self.reduce_binop_chain(expr)?,
+            Expr::Neg { operand } => {
+                match self.reduce_expr(operand)? {
+                    Value::Int(i) => Value::Int(checked(i.checked_neg(), "-")?),
+                    Value::Float(f) => Value::Float(-f),
+                    other => {
+                        return Err(RuntimeError::TypeError(format!("can't negate {}", other.ty().with_article())));
+                    },
+                }
+            },
+            Expr::Not { operand } => Value::Bool(!self.reduce_bool(operand, "operand of !")?),
+            Expr::Block { body, value } => {
+                self.with_scope(|ctx| {
+                    ctx.eval_block(body)?;
+                    match &ctx.unwinding {
+                        Some(Unwind::Return(_) | Unwind::TailCall(_)) => return Err(RuntimeError::ReturnInBlockExpression),
+                        Some(unwind) => return Err(unwind.not_in_loop()),
+                        None => {},
+                    }
+                    match value {
+                        Some(value) => ctx.reduce_expr(value),
+                        None => Ok(Value::Unit),
+                    }
+                })?
+            },
+            Expr::ArrayLit { elements } => {
+                Value::Array(Rc::new(elements.iter().map(|i| self.reduce_expr(i)).collect::<Result<_, _>>()?))
+            },
+            Expr::MapLit { entries } => {
+                let mut map = BTreeMap::new();
+                for (key, value) in entries {
+                    let key = map_key(self.reduce_expr(key)?)?;
+                    map.insert(key, self.reduce_expr(value)?);
+                }
+                Value::Map(Rc::new(map))
+            },
+            Expr::Index { array, index } => {
+                let array = self.reduce_expr(array)?;
+                let index = self.reduce_expr(index)?;
+                element(array, index)?
+            },
+            Expr::IfExpr { condition, then_expr, else_expr } => {
+                if self.reduce_bool(condition, "condition")? {
+                    self.reduce_expr(then_expr)?
+                } else {
+                    self.reduce_expr(else_expr)?
+                }
+            },
+            Expr::Lambda(lambda) => Value::Closure(Rc::new(Closure {
+                lambda: self.global_context.add_lambda(lambda),
+                name: None,
+                frames: ClosureFrames::Captured(self.capture_frames(self.level())),
+            })),
+            Expr::Assign { target, op, value } => {
+                let (variable, slot, indexes) = ast::split_assign_target(target);
+                let indexes = indexes.into_iter()
+                    .map(|index| self.reduce_expr(index))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let val = match op {
+                    Some(op) => {
+                        let mut current = self.read_var(variable, slot)?;
+                        for index in &indexes {
+                            current = element(current, index.clone())?;
+                        }
+                        self.apply_binop(&op.binop(), current, value)?
+                    },
+                    None => self.reduce_expr(value)?,
+                };
+                self.modify_var(variable, slot, |var| assign_element(var, indexes, val.clone()))?;
+                val
+            },
+            Expr::FuncCall { callee, args, span } => match &**callee {
+                // calling a function by name is by far the most common kind of
+                // call, so it goes straight to the function without making a
+                // `Value::Function` to call
+                Expr::VarRef { variable, slot: Slot::Function, .. } => {
+                    let args = self.reduce_args(args)?;
+                    self.global_context.call_func(variable, *span, args.into_iter())?
+                },
+                Expr::VarRef { variable, slot: slot @ (Slot::Local(_) | Slot::Outer { .. }), .. } => {
+                    let callee = self.read_var(variable, *slot)?;
+                    let args = self.reduce_args(args)?;
+                    match callee {
+                        Value::Closure(closure) if closure.frames == ClosureFrames::Live => {
+                            let level = match slot {
+                                Slot::Outer { level, .. } => *level,
+                                _ => self.level(),
+                            };
+                            self.call_local_func(level, &closure, args)?
+                        },
+                        callee => self.global_context.call_value(callee, *span, args.into_iter())?,
+                    }
+                },
+                callee => {
+                    let callee = self.reduce_expr(callee)?;
+                    let args = self.reduce_args(args)?;
+                    self.global_context.call_value(callee, *span, args.into_iter())?
+                },
+            },
+        };
+
+        Ok(val)
+    }
+
+    fn reduce_args(&mut self, args: &'a [Expr]) -> Result<Vec<Value>, RuntimeError> {
+        // args can have side effects, so the left-to-right order matters
+        args.iter().map(|arg| self.reduce_expr(arg)).collect()
+    }
+
+    /// Reduce an expression that has to be a bool. `what` describes the
+    /// expression for the error message.
+    fn reduce_bool(&mut self, expr: &'a Expr, what: &str) -> Result<bool, RuntimeError> {
+        bool_operand(self.reduce_expr(expr)?, what)
     }
 
-    fn eval(&mut self, stmt: &'a Statement) {
+    fn eval(&mut self, stmt: &'a Statement) -> Result<(), RuntimeError> {
         match stmt {
-            Statement::VarDeclaration { variable, value } => {
-                if self.has_var(variable) {
-                    panic!("Redeclaration of variable {variable}");
+            Statement::VarDeclaration { variable, value, is_const, slot, .. } => {
+                let Slot::Local(i) = slot else {
+                    unreachable!("{variable} wasn't resolved to a local before running");
+                };
+                // a fresh declaration's slot is always the end of the frame, and
+                // a redeclaration's is the slot of the variable it redeclares
+                if *i < self.frame.len() {
+                    return Err(RuntimeError::Redeclaration(variable.to_string()));
+                }
+                let val = self.reduce_expr(value)?;
+                self.create_var(variable.clone(), val, *is_const);
+            },
+            Statement::MultiVarDeclaration { declarations } => self.eval_block(declarations)?,
+            // declared when its block started running, and only one that wasn't
+            // callable yet has anything left to do
+            Statement::FuncDef { defined_on_entry: true, .. } => {},
+            Statement::FuncDef { name, func, slot, defined_on_entry: false, .. } => {
+                let Slot::Local(i) = slot else {
+                    unreachable!("{name} wasn't resolved to a local before running");
+                };
+                self.frame[*i].value = Value::Closure(Rc::new(Closure {
+                    lambda: self.global_context.add_lambda(func),
+                    name: Some(name.name().into()),
+                    frames: ClosureFrames::Live,
+                }));
+            },
+            Statement::Return { value, is_tail_call } => {
+                // returning stops execution, so this shouldn't be reachable
+                if self.unwinding.is_some() {
+                    return Err(RuntimeError::ReturnedTwice);
+                }
+                self.unwinding = Some(match value {
+                    Some(Expr::FuncCall { args, .. }) if *is_tail_call => Unwind::TailCall(self.reduce_args(args)?),
+                    Some(value) => Unwind::Return(self.reduce_expr(value)?),
+                    None => Unwind::Return(Value::Unit),
+                });
+            },
+            Statement::Break { .. } => self.unwinding = Some(Unwind::Break),
+            Statement::Continue { .. } => self.unwinding = Some(Unwind::Continue),
+            Statement::ExprStmt { expr } => {
+                self.reduce_expr(expr)?;
+            },
+            Statement::If { condition, then_block, else_block } => {
+                if self.reduce_bool(condition, "condition")? {
+                    self.eval_scoped_block(then_block)?;
+                } else if let Some(else_block) = else_block {
+                    self.eval_scoped_block(else_block)?;
+                }
+            },
+            Statement::While { condition, body } => {
+                while self.unwinding.is_none() && self.reduce_bool(condition, "condition")? {
+                    self.eval_scoped_block(body)?;
+                    if !self.finish_iteration() {
+                        break;
+                    }
+                }
+            },
+            Statement::For { init, condition, step, body } => {
+                self.with_scope(|ctx| {
+                    ctx.eval(init)?;
+                    while ctx.unwinding.is_none() && ctx.reduce_bool(condition, "condition")? {
+                        ctx.eval_scoped_block(body)?;
+                        if !ctx.finish_iteration() {
+                            break;
+                        }
+                        ctx.eval(step)?;
+                    }
+                    Ok(())
+                })?;
+            },
+            // a range literal is iterated without making the whole array
+            Statement::ForEach { variable, iterable: Expr::Range { lhs, rhs }, body, .. } => {
+                for i in self.reduce_range(lhs, rhs, false)? {
+                    if !self.eval_for_each_iteration(variable.clone(), Value::Int(i), body)? {
+                        break;
+                    }
+                }
+            },
+            Statement::ForEach { variable, iterable: Expr::RangeInclusive { lhs, rhs }, body, .. } => {
+                for i in self.reduce_range(lhs, rhs, true)? {
+                    if !self.eval_for_each_iteration(variable.clone(), Value::Int(i), body)? {
+                        break;
+                    }
                 }
-                self.create_var(variable, self.reduce_expr(value));
             },
-            Statement::Assignment { variable, value } => {
-                if !self.has_var(&variable) {
-                    panic!("Variable {variable} is not defined");
+            Statement::ForEach { variable, iterable, body, .. } => {
+                let elements = match self.reduce_expr(iterable)? {
+                    Value::Array(elements) => elements,
+                    other => {
+                        return Err(RuntimeError::TypeError(format!(
+                            "can only iterate over an array, not {}",
+                            other.ty().with_article(),
+                        )));
+                    },
+                };
+                for element in elements.iter() {
+                    if !self.eval_for_each_iteration(variable.clone(), element.clone(), body)? {
+                        break;
+                    }
                 }
+            },
+        }
 
-                *self.get_var_mut(&variable).unwrap() = self.reduce_expr(value);
+        Ok(())
+    }
+
+    /// Run the body of a `for x in ...` loop with `variable` holding `value`,
+    /// returning whether the loop should go on to its next iteration
+    fn eval_for_each_iteration(
+        &mut self,
+        variable: Ident<'a>,
+        value: Value,
+        body: &'a [Statement],
+    ) -> Result<bool, RuntimeError> {
+        self.with_scope(|ctx| {
+            ctx.create_var(variable, value, false);
+            ctx.eval_scoped_block(body)
+        })?;
+        Ok(self.finish_iteration())
+    }
+
+    /// The ints in `lhs..rhs`, or in `lhs..=rhs` if `inclusive`
+    fn reduce_range(
+        &mut self,
+        lhs: &'a Expr,
+        rhs: &'a Expr,
+        inclusive: bool,
+    ) -> Result<RangeInclusive<i64>, RuntimeError> {
+        range_bounds(self.reduce_expr(lhs)?, self.reduce_expr(rhs)?, inclusive)
+    }
+
+    /// Evaluate a list of statements, stopping early if one of them returns, breaks or continues
+    fn eval_block(&mut self, stmts: &'a [Statement]) -> Result<(), RuntimeError> {
+        // like the resolver, declare the functions defined in the block first
+        for stmt in stmts {
+            if let Statement::FuncDef { name, func, slot, defined_on_entry, .. } = stmt {
+                let Slot::Local(i) = slot else {
+                    unreachable!("{name} wasn't resolved to a local before running");
+                };
+                if *i < self.frame.len() {
+                    return Err(RuntimeError::Redeclaration(name.to_string()));
+                }
+                let closure = Closure {
+                    lambda: self.global_context.add_lambda(func),
+                    name: Some(name.name().into()),
+                    frames: if *defined_on_entry { ClosureFrames::Live } else { ClosureFrames::Undefined },
+                };
+                self.create_var(name.clone(), Value::Closure(Rc::new(closure)), true);
+            }
+        }
+
+        for stmt in stmts {
+            self.eval(stmt)?;
+            if self.unwinding.is_some() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle a `break` or `continue` out of a loop body that has just run,
+    /// returning whether the loop should go on to its next iteration
+    fn finish_iteration(&mut self) -> bool {
+        match self.unwinding {
+            Some(Unwind::Break) => {
+                self.unwinding = None;
+                false
             },
-            Statement::Return { value } => {
-                assert!(self.func_ret.is_none(), "function already returned a value");
-                self.func_ret = Some(self.reduce_expr(value));
+            Some(Unwind::Continue) => {
+                self.unwinding = None;
+                true
             },
+            Some(Unwind::Return(_) | Unwind::TailCall(_)) => false,
+            None => true,
+        }
+    }
+
+    /// Type check `args` against the annotations of the function `func_name`'s
+    /// args, and put them in the next slots of the frame
+    fn bind_args(
+        &mut self,
+        func_name: &str,
+        arg_names: &[Ident<'a>],
+        arg_types: &[Option<Type>],
+        args: Vec<Value>,
+    ) -> Result<(), RuntimeError> {
+        for ((name, ty), argval) in arg_names.iter().zip(arg_types).zip(args) {
+            if let Some(ty) = ty {
+                if argval.ty() != *ty {
+                    return Err(RuntimeError::TypeError(format!(
+                        "arg {name} of func {func_name} must be {}, not {}",
+                        ty.with_article(),
+                        argval.type_name(),
+                    )));
+                }
+            }
+            self.create_var(name.clone(), argval, false);
         }
+        Ok(())
+    }
+
+    /// Like `eval_block`, but variables declared in `stmts` go in a new scope
+    fn eval_scoped_block(&mut self, stmts: &'a [Statement]) -> Result<(), RuntimeError> {
+        self.with_scope(|ctx| ctx.eval_block(stmts))
+    }
+
+    /// Run `f` with a new innermost scope, whose variables are dropped afterwards
+    fn with_scope<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let scope_start = self.frame.len();
+        let res = f(self);
+        self.frame.truncate(scope_start);
+        res
+    }
+}
+
+/// The value of an operand that has to be a bool. `what` describes the operand for the error message.
+fn bool_operand(val: Value, what: &str) -> Result<bool, RuntimeError> {
+    match val {
+        Value::Bool(b) => Ok(b),
+        other => Err(RuntimeError::TypeError(format!("{what} must be a bool, not {}", other.type_name()))),
+    }
+}
+
+/// The ints from `start` to `end`, which have to be ints
+fn range_bounds(start: Value, end: Value, inclusive: bool) -> Result<RangeInclusive<i64>, RuntimeError> {
+    match (start, end) {
+        (Value::Int(start), Value::Int(end)) if inclusive => Ok(start..=end),
+        // nothing is below the smallest int, so a range up to it is empty
+        (Value::Int(start), Value::Int(end)) => {
+            Ok(end.checked_sub(1).map_or(RangeInclusive::new(1, 0), |last| start..=last))
+        },
+        (start, end) => Err(RuntimeError::TypeError(format!(
+            "range bounds must be ints, not {} and {}",
+            start.type_name(),
+            end.type_name(),
+        ))),
+    }
+}
+
+/// The ints in `range` as an array, unless there are more than `MAX_RANGE_LEN` of them
+fn range_array(range: RangeInclusive<i64>) -> Result<Value, RuntimeError> {
+    let len = if range.is_empty() { 0 } else { i128::from(*range.end()) - i128::from(*range.start()) + 1 };
+    if len > MAX_RANGE_LEN as i128 {
+        return Err(RuntimeError::RangeTooLong);
+    }
+    Ok(Value::Array(Rc::new(range.map(Value::Int).collect())))
+}
+
+/// `+` concatenates strings, and otherwise does arithmetic
+pub(crate) fn add(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    match (lhs, rhs) {
+        (Value::String(l), Value::String(r)) => Ok(Value::String([&*l, &*r].concat().into())),
+        (l @ Value::String(_), r) | (l, r @ Value::String(_)) => {
+            Err(RuntimeError::TypeError(format!("can't add {} and {}", l.type_name(), r.type_name())))
+        },
+        (l, r) => arith(l, r, |l, r| checked(l.checked_add(r), "+"), |l, r| l + r),
+    }
+}
+
+pub(crate) fn sub(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    arith(lhs, rhs, |l, r| checked(l.checked_sub(r), "-"), |l, r| l - r)
+}
+
+pub(crate) fn mul(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    arith(lhs, rhs, |l, r| checked(l.checked_mul(r), "*"), |l, r| l * r)
+}
+
+pub(crate) fn div(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    arith(lhs, rhs, |l, r| checked_division(l, r, i64::checked_div, "/"), |l, r| l / r)
+}
+
+pub(crate) fn rem(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    arith(lhs, rhs, |l, r| checked_division(l, r, i64::checked_rem, "%"), |l, r| l % r)
+}
+
+pub(crate) fn pow(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    arith(lhs, rhs, int_pow, f64::powf)
+}
+
+/// Apply an arithmetic operator to two numbers. If either is a float, the
+/// other is promoted to a float.
+fn arith(
+    lhs: Value,
+    rhs: Value,
+    int_op: fn(i64, i64) -> Result<i64, RuntimeError>,
+    float_op: fn(f64, f64) -> f64,
+) -> Result<Value, RuntimeError> {
+    let val = match (lhs, rhs) {
+        (Value::Int(l), Value::Int(r)) => Value::Int(int_op(l, r)?),
+        (Value::Int(l), Value::Float(r)) => Value::Float(float_op(l as f64, r)),
+        (Value::Float(l), Value::Int(r)) => Value::Float(float_op(l, r as f64)),
+        (Value::Float(l), Value::Float(r)) => Value::Float(float_op(l, r)),
+        (l, r) => {
+            return Err(RuntimeError::TypeError(
+                format!("can't do arithmetic on {} and {}", l.type_name(), r.type_name())
+            ));
+        },
+    };
+
+    Ok(val)
+}
+
+/// Turn the result of a checked int operation into an `IntegerOverflow` error if it overflowed
+fn checked(result: Option<i64>, op: &'static str) -> Result<i64, RuntimeError> {
+    result.ok_or(RuntimeError::IntegerOverflow { op })
+}
+
+/// Like `checked`, but for `/` and `%`, which fail on a zero divisor as well
+/// as on overflow (`i64::MIN / -1`)
+fn checked_division(
+    l: i64,
+    r: i64,
+    op_fn: fn(i64, i64) -> Option<i64>,
+    op: &'static str,
+) -> Result<i64, RuntimeError> {
+    if r == 0 {
+        Err(RuntimeError::DivisionByZero)
+    } else {
+        checked(op_fn(l, r), op)
+    }
+}
+
+fn int_pow(base: i64, exponent: i64) -> Result<i64, RuntimeError> {
+    if exponent < 0 {
+        return Err(RuntimeError::NegativeExponent);
+    }
+    // these never overflow, however big the exponent is
+    match base {
+        0 => return Ok(if exponent == 0 { 1 } else { 0 }),
+        1 => return Ok(1),
+        -1 => return Ok(if exponent % 2 == 0 { 1 } else { -1 }),
+        _ => {},
+    }
+    // any other base overflows long before the exponent stops fitting in a u32
+    checked(u32::try_from(exponent).ok().and_then(|e| base.checked_pow(e)), "**")
+}
+
+/// Order two numbers, promoting ints to floats like `arith` does. Returns
+/// `None` if either is NaN.
+pub(crate) fn compare(lhs: Value, rhs: Value) -> Result<Option<Ordering>, RuntimeError> {
+    match (lhs, rhs) {
+        (Value::Int(l), Value::Int(r)) => Ok(Some(l.cmp(&r))),
+        (Value::Int(l), Value::Float(r)) => Ok((l as f64).partial_cmp(&r)),
+        (Value::Float(l), Value::Int(r)) => Ok(l.partial_cmp(&(r as f64))),
+        (Value::Float(l), Value::Float(r)) => Ok(l.partial_cmp(&r)),
+        (l, r) => Err(RuntimeError::TypeError(format!("can't compare {} and {}", l.type_name(), r.type_name()))),
+    }
+}
+
+/// Values of different types are never equal, except that ints and floats compare
+/// numerically. Arrays are equal if all their elements are.
+pub(crate) fn values_equal(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Int(l), Value::Float(r)) | (Value::Float(r), Value::Int(l)) => *l as f64 == *r,
+        (Value::Array(l), Value::Array(r)) => {
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| values_equal(l, r))
+        },
+        (Value::Map(l), Value::Map(r)) => {
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|((lk, l), (rk, r))| lk == rk && values_equal(l, r))
+        },
+        (l, r) => l == r,
+    }
+}
+
+/// Check that `index` is an int in bounds for an array of length `len`
+fn array_index(index: Value, len: usize) -> Result<usize, RuntimeError> {
+    match index {
+        Value::Int(i) if usize::try_from(i).is_ok_and(|i| i < len) => Ok(i as usize),
+        Value::Int(i) => Err(RuntimeError::IndexOutOfBounds { index: i, len }),
+        other => Err(RuntimeError::TypeError(format!("array index must be an int, not {}", other.type_name()))),
+    }
+}
+
+/// The element of `array` at `index`, or the value of the key `index` if it's a map
+fn element(array: Value, index: Value) -> Result<Value, RuntimeError> {
+    match array {
+        Value::Array(elements) => {
+            let i = array_index(index, elements.len())?;
+            Ok(elements[i].clone())
+        },
+        Value::Map(entries) => {
+            let key = map_key(index)?;
+            match entries.get(&key) {
+                Some(value) => Ok(value.clone()),
+                None => Err(RuntimeError::MissingKey(key.to_string())),
+            }
+        },
+        other => Err(RuntimeError::TypeError(format!("can't index {}", other.ty().with_article()))),
+    }
+}
+
+/// Assign `val` to the element of `target` that `indexes` lead to, or to `target`
+/// itself if there are none. Arrays and maps on the way that another value shares
+/// are copied first. Only the last index can be a key the map doesn't have yet.
+fn assign_element(target: &mut Value, indexes: Vec<Value>, val: Value) -> Result<(), RuntimeError> {
+    let mut indexes = indexes.into_iter().peekable();
+    let mut elem = target;
+    while let Some(index) = indexes.next() {
+        elem = match elem {
+            Value::Array(elements) => {
+                let i = array_index(index, elements.len())?;
+                &mut Rc::make_mut(elements)[i]
+            },
+            Value::Map(entries) if indexes.peek().is_none() => {
+                Rc::make_mut(entries).insert(map_key(index)?, val);
+                return Ok(());
+            },
+            Value::Map(entries) => {
+                let key = map_key(index)?;
+                match Rc::make_mut(entries).get_mut(&key) {
+                    Some(value) => value,
+                    None => return Err(RuntimeError::MissingKey(key.to_string())),
+                }
+            },
+            other => return Err(RuntimeError::TypeError(format!("can't index {}", other.ty().with_article()))),
+        };
+    }
+    *elem = val;
+    Ok(())
+}
+
+/// Check that `key` can be a map key
+fn map_key(key: Value) -> Result<Rc<str>, RuntimeError> {
+    match key {
+        Value::String(s) => Ok(s),
+        other => Err(RuntimeError::TypeError(format!("map key must be a string, not {}", other.type_name()))),
     }
 }
 
 #[derive(Debug)]
-struct Function<'a> {
-    arg_names: Vec<&'a str>,
-    body: Vec<Statement<'a>>,
+pub(crate) struct Function<'a> {
+    pub(crate) name: Ident<'a>,
+    /// Span of the function's name in its definition
+    name_span: Span,
+    pub(crate) arg_names: Vec<Ident<'a>>,
+    pub(crate) arg_types: Vec<Option<Type>>,
+    pub(crate) return_type: Option<Type>,
+    pub(crate) body: Vec<Statement<'a>>,
 }
 
 impl<'a> Function<'a> {
-    fn new(arg_names: Vec<&'a str>, body: Vec<Statement<'a>>) -> Self {
-        Self { arg_names, body, }
+    /// Run the function's body. A tail call of itself (see `Statement::Return`)
+    /// runs the body again in the same frame, rather than nesting a native call
+    /// and counting towards `MAX_CALL_DEPTH`, so tail recursion can go on indefinitely.
+    fn call(&'a self, args: impl ExactSizeIterator<Item=Value>, global_ctx: &'a GlobalContext<'a>) -> Result<Value, RuntimeError> {
+        let mut ctx = Context::new(global_ctx);
+        let mut args: Vec<Value> = args.collect();
+        let ret = loop {
+            ctx.bind_args(self.name.name(), &self.arg_names, &self.arg_types, args)?;
+            ctx.eval_block(&self.body)?;
+            match ctx.unwinding.take() {
+                Some(Unwind::TailCall(next_args)) => {
+                    if next_args.len() != self.arg_names.len() {
+                        return Err(RuntimeError::ArityMismatch {
+                            func: self.name.to_string(),
+                            expected: self.arg_names.len(),
+                            got: next_args.len(),
+                        });
+                    }
+                    ctx.frame.clear();
+                    args = next_args;
+                },
+                Some(Unwind::Return(ret)) => break ret,
+                Some(unwind) => return Err(unwind.not_in_loop()),
+                // falling off the end of a function returns unit
+                None => break Value::Unit,
+            }
+        };
+
+        check_return_type(self.name.name(), self.return_type, ret)
     }
+}
 
-    fn call(&self, args: impl ExactSizeIterator<Item=Value>, global_ctx: &'a GlobalContext<'a>) -> Value {
-        assert!(self.arg_names.len() == args.len());
-        let mut ctx = Context::new(global_ctx);
-        for (name, argval) in self.arg_names.iter().zip(args) {
-            ctx.create_var(name, argval);
+/// Check the value returned by the function `func_name` against its return type annotation, if it has one
+fn check_return_type(func_name: &str, return_type: Option<Type>, ret: Value) -> Result<Value, RuntimeError> {
+    match return_type {
+        Some(ty) if ret.ty() != ty => Err(RuntimeError::TypeError(format!(
+            "func {func_name} must return {}, not {}",
+            ty.with_article(),
+            ret.type_name(),
+        ))),
+        _ => Ok(ret),
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Builtin {
+    Print,
+    /// Number of elements in an array, or of Unicode scalar values (not bytes) in a string
+    Len,
+    Abs,
+    /// The smaller of two ints, or of two floats. Mixing ints and floats is an error.
+    Min,
+    /// The larger of two ints, or of two floats. Mixing ints and floats is an error.
+    Max,
+    /// The name of a value's type, as a string: `type(1)` is `"int"`
+    Type,
+    /// Truncate a float toward zero, or parse a string, into an int
+    ToInt,
+    /// Widen an int, or parse a string, into a float
+    ToFloat,
+}
+
+impl Builtin {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "print" => Some(Self::Print),
+            "len" => Some(Self::Len),
+            "abs" => Some(Self::Abs),
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            "type" => Some(Self::Type),
+            "to_int" => Some(Self::ToInt),
+            "to_float" => Some(Self::ToFloat),
+            _ => None,
         }
+    }
 
-        for stmt in self.body.iter() {
-            ctx.eval(stmt);
-            if ctx.func_ret.is_some() {
-                break;
-            }
+    /// How many args the builtin takes
+    pub(crate) fn arity(&self) -> usize {
+        match self {
+            Self::Print | Self::Len | Self::Abs | Self::Type | Self::ToInt | Self::ToFloat => 1,
+            Self::Min | Self::Max => 2,
+        }
+    }
+
+    pub(crate) fn call(&self, args: Vec<Value>, output: &Output) -> Result<Value, RuntimeError> {
+        match self {
+            Self::Print => {
+                let [val] = take_args("print", args)?;
+                output.print(&val)?;
+                Ok(Value::Unit)
+            },
+            Self::Len => {
+                let len = match take_args("len", args)? {
+                    [Value::Array(elements)] => elements.len(),
+                    [Value::Map(entries)] => entries.len(),
+                    [Value::String(s)] => s.chars().count(),
+                    [other] => {
+                        return Err(RuntimeError::TypeError(format!("can't take the len of {}", other.ty().with_article())));
+                    },
+                };
+                Ok(Value::Int(len as i64))
+            },
+            Self::Abs => match take_args("abs", args)? {
+                [Value::Int(i)] => Ok(Value::Int(checked(i.checked_abs(), "abs")?)),
+                [Value::Float(x)] => Ok(Value::Float(x.abs())),
+                [other] => Err(RuntimeError::TypeError(format!("can't take the abs of {}", other.ty().with_article()))),
+            },
+            Self::Min => min_max("min", args, i64::min, f64::min),
+            Self::Max => min_max("max", args, i64::max, f64::max),
+            Self::Type => {
+                let [val] = take_args("type", args)?;
+                Ok(Value::String(val.type_name().into()))
+            },
+            Self::ToInt => match take_args("to_int", args)? {
+                [Value::Int(i)] => Ok(Value::Int(i)),
+                // `as` would saturate, and turn NaN into 0
+                [Value::Float(x)] if x.trunc() >= i64::MIN as f64 && x.trunc() < -(i64::MIN as f64) => {
+                    Ok(Value::Int(x as i64))
+                },
+                [Value::Float(_)] => Err(RuntimeError::IntegerOverflow { op: "to_int" }),
+                [Value::String(s)] => s.trim().parse().map(Value::Int).map_err(|_| RuntimeError::ParseValueError {
+                    text: s.to_string(),
+                    ty: Type::Int,
+                }),
+                [other] => Err(RuntimeError::TypeError(format!("can't convert {} to an int", other.ty().with_article()))),
+            },
+            Self::ToFloat => match take_args("to_float", args)? {
+                [Value::Int(i)] => Ok(Value::Float(i as f64)),
+                [Value::Float(x)] => Ok(Value::Float(x)),
+                [Value::String(s)] => s.trim().parse().map(Value::Float).map_err(|_| RuntimeError::ParseValueError {
+                    text: s.to_string(),
+                    ty: Type::Float,
+                }),
+                [other] => Err(RuntimeError::TypeError(format!("can't convert {} to a float", other.ty().with_article()))),
+            },
         }
+    }
+}
 
-        ctx.func_ret.expect("Function did not return a value")
+fn min_max(
+    func_name: &str,
+    args: Vec<Value>,
+    int_op: fn(i64, i64) -> i64,
+    float_op: fn(f64, f64) -> f64,
+) -> Result<Value, RuntimeError> {
+    match take_args(func_name, args)? {
+        [Value::Int(a), Value::Int(b)] => Ok(Value::Int(int_op(a, b))),
+        [Value::Float(a), Value::Float(b)] => Ok(Value::Float(float_op(a, b))),
+        [a, b] => Err(RuntimeError::TypeError(
+            format!("{func_name} needs two ints or two floats, not {} and {}", a.type_name(), b.type_name())
+        )),
     }
 }
 
-#[derive(Debug)]
-enum Builtin {
-    Print,
+/// Unpack the args to a builtin, checking that there are exactly `N` of them
+fn take_args<const N: usize>(func_name: &str, args: Vec<Value>) -> Result<[Value; N], RuntimeError> {
+    args.try_into().map_err(|args: Vec<Value>| RuntimeError::ArityMismatch {
+        func: func_name.to_string(),
+        expected: N,
+        got: args.len(),
+    })
 }
 
-#[derive(Debug)]
-enum FunctionOrBuiltin<'a> {
-    Function(Function<'a>),
-    Builtin(Builtin),
+/// Where `print` writes to. A borrowed writer rather than a boxed one, since
+/// `Program::execute` borrows the program for as long as its AST, which it can't
+/// do if dropping it might use the writer.
+pub(crate) enum Output<'a> {
+    Stdout,
+    Writer(RefCell<&'a mut dyn Write>),
+}
+
+impl Output<'_> {
+    /// Write `value` on a line of its own
+    fn print(&self, value: &Value) -> Result<(), RuntimeError> {
+        match self {
+            Self::Stdout => writeln!(io::stdout(), "{value}"),
+            Self::Writer(writer) => writeln!(writer.borrow_mut(), "{value}"),
+        }.map_err(RuntimeError::Output)
+    }
+}
+
+impl std::fmt::Debug for Output<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Stdout => f.write_str("Stdout"),
+            Self::Writer(_) => f.write_str("Writer"),
+        }
+    }
 }
 
 #[derive(Debug)]
-struct GlobalContext<'a> {
-    functions: HashMap<&'a str, FunctionOrBuiltin<'a>>,
+pub(crate) struct GlobalContext<'a> {
+    pub(crate) functions: IdentMap<'a, Function<'a>>,
+    /// The key of each function in `functions`, for calling a `Value::Function`
+    function_names: HashMap<String, Ident<'a>>,
+    globals: RefCell<IdentMap<'a, Variable<'a>>>,
+    /// Every lambda and function defined in a block that has been run, keyed by
+    /// its address, which is what a `Closure` refers to it by
+    lambdas: RefCell<HashMap<usize, &'a Lambda<'a>>>,
+    /// How many user function and lambda calls are currently in progress
+    call_depth: Cell<usize>,
+    output: Output<'a>,
 }
 
 impl<'a> GlobalContext<'a> {
     fn new() -> Self {
-        let mut builtins = HashMap::new();
-        builtins.insert("print", FunctionOrBuiltin::Builtin(Builtin::Print));
-
         Self {
-            functions: builtins,
+            functions: IdentMap::default(),
+            function_names: HashMap::new(),
+            globals: RefCell::new(IdentMap::default()),
+            lambdas: RefCell::new(HashMap::new()),
+            call_depth: Cell::new(0),
+            output: Output::Stdout,
         }
     }
 
-    fn call_func(&'a self, func_name: &'a str, args: impl ExactSizeIterator<Item=Value>) -> Value {
-        let f = self.functions.get(func_name).expect(&format!("no defintion for func {func_name}"));
-        match f {
-            FunctionOrBuiltin::Function(f) => f.call(args, self),
-            FunctionOrBuiltin::Builtin(Builtin::Print) => {
-                for i in args {
-                    println!("{}", i.to_string());
-                }
+    fn call_func(
+        &'a self,
+        func_name: &Ident<'a>,
+        call_span: Span,
+        args: impl ExactSizeIterator<Item=Value>,
+    ) -> Result<Value, RuntimeError> {
+        // user functions can't be named the same as builtins, so it doesn't matter
+        // which is checked first, but comparing symbols is quicker than names
+        let Some(f) = self.functions.get(func_name) else {
+            return match Builtin::from_name(func_name.name()) {
+                Some(builtin) => builtin.call(args.collect(), &self.output),
+                None => Err(RuntimeError::UndefinedFunction { name: func_name.to_string(), span: call_span }),
+            };
+        };
+        if f.arg_names.len() != args.len() {
+            return Err(RuntimeError::ArityMismatch {
+                func: func_name.to_string(),
+                expected: f.arg_names.len(),
+                got: args.len(),
+            });
+        }
 
-                Value::Int(0)
-            }
+        self.nested_call(|| f.call(args, self))
+    }
+
+    /// Run `call` as a call of a user function or lambda, failing if too many are already in progress
+    fn nested_call(&self, call: impl FnOnce() -> Result<Value, RuntimeError>) -> Result<Value, RuntimeError> {
+        let depth = self.call_depth.get();
+        if depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::StackOverflow);
+        }
+        self.call_depth.set(depth + 1);
+        let res = stacker::maybe_grow(CALL_RED_ZONE, CALL_STACK_SIZE, call);
+        self.call_depth.set(depth);
+        res
+    }
+
+    /// The key `lambda` is kept under in `lambdas`. Values can't borrow from the
+    /// AST, so a closure refers to its lambda by address.
+    fn add_lambda(&self, lambda: &'a Lambda<'a>) -> usize {
+        let key = std::ptr::from_ref(lambda) as usize;
+        self.lambdas.borrow_mut().entry(key).or_insert(lambda);
+        key
+    }
+
+    /// The lambda of `closure`, checking that it takes as many args as `args`
+    fn lambda(&self, closure: &Closure, args: &[Value]) -> Result<&'a Lambda<'a>, RuntimeError> {
+        let lambda = self.lambdas.borrow()[&closure.lambda];
+        if lambda.arg_names.len() != args.len() {
+            return Err(RuntimeError::ArityMismatch {
+                func: closure.name().to_string(),
+                expected: lambda.arg_names.len(),
+                got: args.len(),
+            });
         }
+        Ok(lambda)
     }
 
-    fn add_func(&mut self, func_name: &'a str, func: Function<'a>) {
-        assert!(!self.functions.contains_key(func_name), "func {func_name} already defined");
-        self.functions.insert(func_name, FunctionOrBuiltin::Function(func));
+    fn call_closure(&'a self, closure: &Closure, call_span: Span, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let frames = match &closure.frames {
+            ClosureFrames::Captured(frames) => frames,
+            ClosureFrames::Undefined => {
+                return Err(RuntimeError::UndefinedFunction { name: closure.name().to_string(), span: call_span });
+            },
+            ClosureFrames::Live => unreachable!("a function defined in a block is only used as a value as a copy"),
+        };
+        let lambda = self.lambda(closure, &args)?;
+
+        self.nested_call(|| {
+            let mut ctx = Context::new(self);
+            ctx.outer = frames.iter().cloned().map(OuterFrame::Captured).collect();
+            ctx.run_lambda(closure.name(), lambda, args)
+        })
     }
+
+    /// Call `callee`, which has to be a function or closure
+    fn call_value(
+        &'a self,
+        callee: Value,
+        call_span: Span,
+        args: impl ExactSizeIterator<Item=Value>,
+    ) -> Result<Value, RuntimeError> {
+        match callee {
+            Value::Function(name) => match self.function_names.get(&*name) {
+                Some(func_name) => self.call_func(func_name, call_span, args),
+                None => Builtin::from_name(&name)
+                    .expect("function values are only made for functions that exist")
+                    .call(args.collect(), &self.output),
+            },
+            Value::Closure(closure) => self.call_closure(&closure, call_span, args.collect()),
+            other => Err(RuntimeError::NotCallable { ty: other.ty(), span: call_span }),
+        }
+    }
+
+    /// The names of the globals and functions defined so far, for resolving more code against
+    fn names(&self) -> GlobalNames<'a> {
+        GlobalNames {
+            vars: self.globals.borrow().keys().cloned().collect(),
+            functions: self.functions.keys().cloned().collect(),
+        }
+    }
+
+    /// How many args the builtin or user function `func_name` takes, if it exists
+    pub(crate) fn arity(&self, func_name: &Ident) -> Option<usize> {
+        match Builtin::from_name(func_name.name()) {
+            Some(builtin) => Some(builtin.arity()),
+            None => self.functions.get(func_name).map(|f| f.arg_names.len()),
+        }
+    }
+
+    fn add_func(&mut self, func_name: Ident<'a>, func: Function<'a>) -> Result<(), ProgramError> {
+        if Builtin::from_name(func_name.name()).is_some() {
+            return Err(ProgramError::RedefinedBuiltin { name: func_name.to_string(), span: func.name_span });
+        }
+        if let Some(existing) = self.functions.get(&func_name) {
+            return Err(ProgramError::DuplicateFunction {
+                name: func_name.to_string(),
+                first_span: existing.name_span,
+                second_span: func.name_span,
+            });
+        }
+        self.function_names.insert(func_name.to_string(), func_name.clone());
+        self.functions.insert(func_name, func);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct GlobalVar<'a> {
+    pub(crate) name: Ident<'a>,
+    pub(crate) span: Span,
+    pub(crate) value: Expr<'a>,
+    pub(crate) is_const: bool,
 }
 
 #[derive(Debug)]
 pub struct Program<'a> {
-    begin_body: Vec<Statement<'a>>,
-    global: GlobalContext<'a>,
+    pub(crate) begin_body: Vec<Statement<'a>>,
+    /// Initialized in order before `begin` runs
+    pub(crate) global_vars: Vec<GlobalVar<'a>>,
+    pub(crate) global: GlobalContext<'a>,
 }
 
 impl<'a> Program<'a> {
-    pub fn from_items(items: impl Iterator<Item=Item<'a>>) -> Self {
+    pub fn from_items(items: impl Iterator<Item=Item<'a>>) -> Result<Self, ProgramError> {
         let mut begin_body = None;
+        let mut global_vars = Vec::new();
 
         let mut global = GlobalContext::new();
 
         for i in items {
             match i {
-                Item::EntryBlock { body } => {
-                    assert!(begin_body.is_none(), "Multiple begin blocks not allowed");
-                    begin_body = Some(body);
+                Item::EntryBlock { span, body } => {
+                    if let Some((first_span, _)) = begin_body {
+                        return Err(ProgramError::MultipleBegin { first_span, second_span: span });
+                    }
+                    begin_body = Some((span, body));
+                },
+                Item::FuncDef { name, name_span, arg_names, arg_types, return_type, body } => {
+                    let func = Function { name: name.clone(), name_span, arg_names, arg_types, return_type, body };
+                    global.add_func(name, func)?;
                 },
-                Item::FuncDef { name, arg_names, body } => {
-                    global.add_func(name, Function::new(arg_names, body));
+                Item::GlobalVar { variable, span, value, is_const } => {
+                    global_vars.push(GlobalVar { name: variable, span, value, is_const });
                 },
             }
         }
+        let mut begin_body = begin_body.ok_or(ProgramError::MissingBegin)?.1;
+
+        // a name can refer to a global or function defined anywhere in the
+        // program, so nothing can be resolved until they're all known
+        let names = GlobalNames {
+            vars: global_vars.iter().map(|var| var.name.clone()).collect(),
+            functions: global.functions.keys().cloned().collect(),
+        };
+        Resolver::new(&names, Vec::new()).resolve_block(&mut begin_body);
+        for func in global.functions.values_mut() {
+            Resolver::for_function(&names, func.name.clone(), func.arg_names.clone()).resolve_block(&mut func.body);
+        }
+        for var in &mut global_vars {
+            // initializers run in a frame of their own, for any block expressions in them
+            Resolver::new(&names, Vec::new()).resolve_expr(&mut var.value);
+        }
 
+        Ok(Self { begin_body, global_vars, global })
+    }
+
+    /// Make `print` write to `output` instead of stdout, like to a `Vec<u8>` to capture what the program prints
+    pub fn with_output(mut self, output: &'a mut dyn Write) -> Self {
+        self.global.output = Output::Writer(RefCell::new(output));
+        self
+    }
+
+    /// A program with no functions or globals and an empty begin block, for running REPL input in
+    pub fn empty() -> Self {
         Self {
-            begin_body: begin_body.expect("Begin block is missing"),
-            global,
+            begin_body: Vec::new(),
+            global_vars: Vec::new(),
+            global: GlobalContext::new(),
         }
     }
 
-    pub fn execute(&'a mut self) {
+    /// A context outside of any function, for running REPL input in
+    pub fn top_level_context(&'a self) -> Context<'a> {
+        Context::new(&self.global)
+    }
+
+    /// Run the `begin` block, returning its context so the final values of its variables can be read
+    pub fn execute(&'a self) -> Result<Context<'a>, RuntimeError> {
         let mut ctx = Context::new(&self.global);
-        for stmt in self.begin_body.iter() {
-            if matches!(stmt, Statement::Return { .. }) {
-                panic!("Can't return from begin block");
+
+        for GlobalVar { name, value, is_const, .. } in &self.global_vars {
+            let value = ctx.reduce_expr(value)?;
+            let mut globals = self.global.globals.borrow_mut();
+            if globals.contains_key(name) {
+                return Err(RuntimeError::Redeclaration(name.to_string()));
             }
-            ctx.eval(stmt);
+            globals.insert(name.clone(), Variable { name: name.clone(), value, is_const: *is_const });
+        }
+
+        ctx.eval_block(&self.begin_body)?;
+
+        match ctx.unwinding.take() {
+            Some(Unwind::Return(_) | Unwind::TailCall(_)) => Err(RuntimeError::ReturnOutsideFunction),
+            Some(unwind) => Err(unwind.not_in_loop()),
+            None => Ok(ctx),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-enum Value {
-    Int(u32),
+/// A runtime value. Only `PartialEq`, since floats can be NaN.
+///
+/// Strings, arrays and maps are reference-counted, so cloning a value (like
+/// reading a variable does) is cheap. They still act like they're copied:
+/// assigning to an element of an array that shares its elements copies them first.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(Rc<str>),
+    Array(Rc<Vec<Value>>),
+    /// Sorted by key, so a map always prints the same way
+    Map(Rc<BTreeMap<Rc<str>, Value>>),
+    /// A user function or builtin, by name. A function's name used as a variable
+    /// gives one of these.
+    Function(Rc<str>),
+    /// What a lambda evaluates to
+    Closure(Rc<Closure>),
+    /// The value of things that don't produce anything
+    Unit,
 }
 
-impl ToString for Value {
-    fn to_string(&self) -> String {
+/// A lambda or a function defined in a block, along with the frames it's nested in
+#[derive(Debug, PartialEq)]
+pub struct Closure {
+    /// The address of its `Lambda`, as its key in `GlobalContext::lambdas`
+    lambda: usize,
+    /// The name of a function defined in a block, which lambdas don't have
+    name: Option<Rc<str>>,
+    frames: ClosureFrames,
+}
+
+/// Where a closure gets the locals of the frames it's nested in from
+#[derive(Debug, PartialEq)]
+enum ClosureFrames {
+    /// Copies of them, outermost first, taken when a lambda is evaluated or a
+    /// function defined in a block is used as a value
+    Captured(Vec<Rc<[Value]>>),
+    /// The frames of the call in progress, for a function defined in a block
+    /// that's called by name. Only its variable holds one of these.
+    Live,
+    /// A function defined in a block that can't be called until its definition
+    /// has run, which it hasn't yet. See `Statement::FuncDef`.
+    Undefined,
+}
+
+impl Closure {
+    /// What the closure is called in error messages
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(LAMBDA_NAME)
+    }
+}
+
+impl Value {
+    pub(crate) fn ty(&self) -> Type {
         match self {
-            Self::Int(i) => i.to_string(),
+            Self::Int(_) => Type::Int,
+            Self::Float(_) => Type::Float,
+            Self::Bool(_) => Type::Bool,
+            Self::String(_) => Type::String,
+            Self::Array(_) => Type::Array,
+            Self::Map(_) => Type::Map,
+            Self::Function(_) | Self::Closure(_) => Type::Function,
+            Self::Unit => Type::Unit,
         }
     }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        self.ty().name()
+    }
+}
+
+/// The text `print` and string interpolation produce for a value. Strings are
+/// written as-is, except inside arrays and maps, where they're quoted like
+/// literals so that `["a, b"]` and `["a", "b"]` look different.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Int(i) => write!(f, "{i}"),
+            // always with a decimal point and never in exponent form, like a literal
+            Self::Float(x) if x.is_finite() && x.fract() == 0.0 => write!(f, "{x}.0"),
+            Self::Float(x) => write!(f, "{x}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::String(s) => write!(f, "{s}"),
+            Self::Array(elements) => {
+                write!(f, "[")?;
+                for (i, elem) in elements.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_element(f, elem)?;
+                }
+                write!(f, "]")
+            },
+            Self::Map(entries) => {
+                write!(f, "#{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_element(f, &Self::String(key.clone()))?;
+                    write!(f, ": ")?;
+                    write_element(f, value)?;
+                }
+                write!(f, "}}")
+            },
+            Self::Function(name) => write!(f, "<function {name}>"),
+            Self::Closure(closure) => write!(f, "<function {}>", closure.name()),
+            Self::Unit => write!(f, "()"),
+        }
+    }
+}
+
+/// Write a value inside an array or map, quoting it if it's a string
+fn write_element(f: &mut std::fmt::Formatter, value: &Value) -> std::fmt::Result {
+    if let Value::String(s) = value {
+        write!(f, "\"")?;
+        crate::format::write_escaped(f, s)?;
+        write!(f, "\"")
+    } else {
+        write!(f, "{value}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_pow_of_trivial_bases_never_overflows() {
+        assert_eq!(int_pow(1, i64::MAX).unwrap(), 1);
+        assert_eq!(int_pow(0, 4294967296).unwrap(), 0);
+        assert_eq!(int_pow(0, 0).unwrap(), 1);
+        assert_eq!(int_pow(-1, 4294967297).unwrap(), -1);
+        assert_eq!(int_pow(-1, 4294967296).unwrap(), 1);
+    }
+
+    #[test]
+    fn int_pow_overflow() {
+        assert_eq!(int_pow(2, 62).unwrap(), 1 << 62);
+        assert!(matches!(int_pow(2, 63), Err(RuntimeError::IntegerOverflow { .. })));
+        assert!(matches!(int_pow(2, 4294967296), Err(RuntimeError::IntegerOverflow { .. })));
+        assert!(matches!(int_pow(2, -1), Err(RuntimeError::NegativeExponent)));
+    }
+
+    #[test]
+    fn range_array_length_limit() {
+        assert!(matches!(range_array(0..=i64::MAX), Err(RuntimeError::RangeTooLong)));
+        assert!(matches!(range_array(i64::MIN..=i64::MAX), Err(RuntimeError::RangeTooLong)));
+        assert!(matches!(range_array(1..=MAX_RANGE_LEN as i64 + 1), Err(RuntimeError::RangeTooLong)));
+        assert_eq!(range_array(RangeInclusive::new(5, 4)).unwrap().to_string(), "[]");
+        assert_eq!(range_array(-1..=1).unwrap().to_string(), "[-1, 0, 1]");
+    }
+
+    #[test]
+    fn only_ranges_used_as_values_are_limited() {
+        let result = crate::run_source("begin { var r = 0..100000000000; }");
+        assert!(matches!(result, Err(crate::Error::Runtime(RuntimeError::RangeTooLong))));
+        let vars = crate::run_source("begin { var n = 0; for i in 0..100000000000 { n = i; if i == 3 { break; } } }").unwrap();
+        assert_eq!(vars["n"], Value::Int(3));
+    }
 }
@@ -0,0 +1,211 @@
+//! Dumping a parsed program as JSON, for external tools, and loading it back
+//! to run without lexing and parsing the source again.
+//!
+//! The layout is the one serde's derives give by default. Each enum value
+//! is an object whose only key is the variant's name, holding its fields. A
+//! unit variant is just its name as a string, and an `Ident` is its name.
+//! `Slot`s and `is_tail_call` are left out, since they're filled in by the
+//! interpreter rather than parsed.
+
+use crate::ast::{self, Expr, Interner, Item, Statement, StringPart};
+use serde::de::Error as _;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    /// Where deserialized idents get their symbols from. `ast_from_json` starts
+    /// each load with a fresh one, so names don't pile up across loads.
+    pub(crate) static DESERIALIZE_INTERNER: RefCell<Interner> = RefCell::default();
+}
+
+/// How close to the end of the stack serializing or deserializing can get before
+/// it moves to a new stack of `GROWN_STACK_SIZE`
+const RED_ZONE: usize = 64 * 1024;
+const GROWN_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+/// The items of a program as a JSON array
+pub fn ast_to_json(items: &[Item]) -> String {
+    serde_json::to_string(items).expect("an AST always serializes")
+}
+
+/// Load items written by `ast_to_json`, so a program can run without being
+/// lexed and parsed again. The items own their names, so they don't borrow `json`.
+/// Items that the parser couldn't have given are an error, like ones nested
+/// deeper than `ast::DEFAULT_MAX_DEPTH` allows, or with a keyword as a name.
+pub fn ast_from_json(json: &str) -> Result<Vec<Item<'static>>, serde_json::Error> {
+    let mut json_deserializer = serde_json::Deserializer::from_str(json);
+    // operator chains nest deeper than serde_json allows, and validating the items limits them
+    json_deserializer.disable_recursion_limit();
+    let mut deserializer = serde_stacker::Deserializer::new(&mut json_deserializer);
+    deserializer.red_zone = RED_ZONE;
+    deserializer.stack_size = GROWN_STACK_SIZE;
+
+    let outer = DESERIALIZE_INTERNER.take();
+    let items = Vec::<Item>::deserialize(deserializer);
+    DESERIALIZE_INTERNER.set(outer);
+    let items = items?;
+    json_deserializer.end()?;
+    validate_items(&items).map_err(serde_json::Error::custom)?;
+    Ok(items)
+}
+
+/// Serialize the lhs of a binary operation, on a new stack if this one is running out
+pub(crate) fn serialize_lhs<S: serde::Serializer>(lhs: &Expr, serializer: S) -> Result<S::Ok, S::Error> {
+    stacker::maybe_grow(RED_ZONE, GROWN_STACK_SIZE, || lhs.serialize(serializer))
+}
+
+/// serde_json writes the infinity a too-big float literal gives as null, so read that back as infinity.
+/// Float literals are never negative, since `-` is parsed as an operator.
+pub(crate) fn deserialize_float_lit<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::INFINITY))
+}
+
+/// Check the shape of loaded items, which the interpreter relies on. Nesting is
+/// counted like the parser counts it, where each expression, and each block
+/// statements are in, is a level deeper, except that a binary operation's
+/// lhs is as deep as the operation. Chains are limited to `ast::MAX_CHAIN_LENGTH`.
+fn validate_items(items: &[Item]) -> Result<(), String> {
+    for item in items {
+        match item {
+            Item::EntryBlock { body, .. } | Item::FuncDef { body, .. } => validate_block(body, 1)?,
+            Item::GlobalVar { value, .. } => validate_expr(value, 1)?,
+        }
+    }
+    Ok(())
+}
+
+/// Validate a block whose statements are at `depth`
+fn validate_block(stmts: &[Statement], depth: usize) -> Result<(), String> {
+    if depth > ast::DEFAULT_MAX_DEPTH {
+        return Err("the AST is nested too deeply".to_string());
+    }
+    for stmt in stmts {
+        validate_statement(stmt, depth)?;
+    }
+    Ok(())
+}
+
+/// Validate a statement in a block at `depth`, whose expressions are a level deeper
+fn validate_statement(stmt: &Statement, depth: usize) -> Result<(), String> {
+    match stmt {
+        Statement::VarDeclaration { value, .. } | Statement::ExprStmt { expr: value } => validate_expr(value, depth + 1),
+        Statement::MultiVarDeclaration { declarations } => {
+            for decl in declarations {
+                if !matches!(decl, Statement::VarDeclaration { .. }) {
+                    return Err("a multiple declaration can only have declarations in it".to_string());
+                }
+                validate_statement(decl, depth)?;
+            }
+            Ok(())
+        },
+        Statement::Return { value: Some(value), .. } => validate_expr(value, depth + 1),
+        Statement::Return { value: None, .. } | Statement::Break { .. } | Statement::Continue { .. } => Ok(()),
+        Statement::FuncDef { func, .. } => validate_block(&func.body, depth + 1),
+        Statement::If { condition, then_block, else_block } => {
+            validate_expr(condition, depth + 1)?;
+            validate_block(then_block, depth + 1)?;
+            match else_block {
+                Some(else_block) => validate_block(else_block, depth + 1),
+                None => Ok(()),
+            }
+        },
+        Statement::While { condition, body } => {
+            validate_expr(condition, depth + 1)?;
+            validate_block(body, depth + 1)
+        },
+        Statement::For { init, condition, step, body } => {
+            if is_block_statement(init) || is_block_statement(step) {
+                return Err("a for loop's init and step can't be block statements".to_string());
+            }
+            validate_statement(init, depth)?;
+            validate_expr(condition, depth + 1)?;
+            validate_statement(step, depth)?;
+            validate_block(body, depth + 1)
+        },
+        Statement::ForEach { iterable, body, .. } => {
+            validate_expr(iterable, depth + 1)?;
+            validate_block(body, depth + 1)
+        },
+    }
+}
+
+fn is_block_statement(stmt: &Statement) -> bool {
+    matches!(
+        stmt,
+        Statement::If { .. } | Statement::While { .. } | Statement::For { .. } | Statement::ForEach { .. } | Statement::FuncDef { .. },
+    )
+}
+
+/// Validate an expression at `depth`
+fn validate_expr(expr: &Expr, depth: usize) -> Result<(), String> {
+    if depth > ast::DEFAULT_MAX_DEPTH {
+        return Err("the AST is nested too deeply".to_string());
+    }
+    let deeper = depth + 1;
+    match expr {
+        Expr::IntLit { .. }
+            | Expr::FloatLit { .. }
+            | Expr::BoolLit { .. }
+            | Expr::StringLit { .. }
+            | Expr::VarRef { .. } => Ok(()),
+        Expr::InterpolatedString { parts } => {
+            for part in parts {
+                if let StringPart::Expr(expr) = part {
+                    validate_expr(expr, deeper)?;
+                }
+            }
+            Ok(())
+        },
+        Expr::Neg { operand } | Expr::Not { operand } => validate_expr(operand, deeper),
+        Expr::Block { body, value } => {
+            // the statements aren't a level deeper than the block, like a block statement's are
+            for stmt in body {
+                validate_statement(stmt, depth)?;
+            }
+            match value {
+                Some(value) => validate_expr(value, deeper),
+                None => Ok(()),
+            }
+        },
+        Expr::ArrayLit { elements } => elements.iter().try_for_each(|elem| validate_expr(elem, deeper)),
+        Expr::MapLit { entries } => entries.iter().try_for_each(|(key, value)| {
+            validate_expr(key, deeper)?;
+            validate_expr(value, deeper)
+        }),
+        Expr::Index { array, index } => {
+            validate_expr(array, deeper)?;
+            validate_expr(index, deeper)
+        },
+        Expr::IfExpr { condition, then_expr, else_expr } => {
+            validate_expr(condition, deeper)?;
+            validate_expr(then_expr, deeper)?;
+            validate_expr(else_expr, deeper)
+        },
+        Expr::Lambda(lambda) => validate_block(&lambda.body, deeper),
+        Expr::Assign { target, value, .. } => {
+            if !ast::is_assignable(target) {
+                return Err("only a variable or an element of one can be assigned to".to_string());
+            }
+            validate_expr(target, deeper)?;
+            validate_expr(value, deeper)
+        },
+        Expr::FuncCall { callee, args, .. } => {
+            validate_expr(callee, deeper)?;
+            args.iter().try_for_each(|arg| validate_expr(arg, deeper))
+        },
+        _ => {
+            // walk the chain down the lhs, like `Context::reduce_binop_chain`
+            let mut length = 0;
+            let mut leftmost = expr;
+            while let Some((lhs, _, rhs)) = ast::split_binop(leftmost) {
+                length += 1;
+                if length > ast::MAX_CHAIN_LENGTH {
+                    return Err("an operator chain is longer than the parser allows".to_string());
+                }
+                validate_expr(rhs, deeper)?;
+                leftmost = lhs;
+            }
+            validate_expr(leftmost, depth)
+        },
+    }
+}
@@ -1,31 +1,281 @@
-#[derive(Debug)]
+/// A token, borrowing any text it carries (identifiers, literals) from the
+/// source string with lifetime `'a`
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token<'a> {
     Begin,
     LeftBrace,
+    /// `#{`, which opens a map literal
+    HashLeftBrace,
     RightBrace,
     Var,
     Ident(&'a str),
     Equals,
+    /// The text of an int literal, including any radix prefix like `0x`
+    /// and `_` digit separators
     Integer(&'a str),
+    Float(&'a str),
+    /// The raw contents between the quotes, escapes and `${}` interpolations
+    /// and all. See `unescape`.
+    StringLit(&'a str),
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
     Plus,
+    Minus,
+    Star,
+    StarStar,
+    Slash,
+    Percent,
     Semicolon,
+    Colon,
+    Arrow,
+    DotDot,
+    DotDotEquals,
     Comma,
+    Bang,
+    DoubleEquals,
+    NotEquals,
+    Less,
+    Greater,
+    LessEquals,
+    GreaterEquals,
+    PlusEquals,
+    MinusEquals,
+    StarEquals,
+    SlashEquals,
+    AndAnd,
+    OrOr,
     Return,
     Func,
+    True,
+    False,
+    If,
+    Then,
+    Else,
+    While,
+    For,
+    In,
+    Const,
+    Break,
+    Continue,
+}
+
+/// Reserved words, which lex as their own token instead of as `Token::Ident`
+pub const KEYWORDS: &[(&str, Token<'static>)] = &[
+    ("begin", Token::Begin),
+    ("var", Token::Var),
+    ("return", Token::Return),
+    ("func", Token::Func),
+    ("true", Token::True),
+    ("false", Token::False),
+    ("if", Token::If),
+    ("then", Token::Then),
+    ("else", Token::Else),
+    ("while", Token::While),
+    ("for", Token::For),
+    ("in", Token::In),
+    ("const", Token::Const),
+    ("break", Token::Break),
+    ("continue", Token::Continue),
+];
+
+impl Token<'_> {
+    /// The word this token is lexed from, if it's a keyword
+    pub fn keyword(&self) -> Option<&'static str> {
+        KEYWORDS.iter().find(|(_, tkn)| tkn == self).map(|(kw, _)| *kw)
+    }
+}
+
+/// The token as it's written in the source
+impl std::fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let text = match self {
+            Self::Ident(text) | Self::Integer(text) | Self::Float(text) => text,
+            Self::StringLit(raw) => return write!(f, "\"{raw}\""),
+            Self::LeftBrace => "{",
+            Self::HashLeftBrace => "#{",
+            Self::RightBrace => "}",
+            Self::Equals => "=",
+            Self::LeftParen => "(",
+            Self::RightParen => ")",
+            Self::LeftBracket => "[",
+            Self::RightBracket => "]",
+            Self::Plus => "+",
+            Self::Minus => "-",
+            Self::Star => "*",
+            Self::StarStar => "**",
+            Self::Slash => "/",
+            Self::Percent => "%",
+            Self::Semicolon => ";",
+            Self::Colon => ":",
+            Self::Arrow => "->",
+            Self::DotDot => "..",
+            Self::DotDotEquals => "..=",
+            Self::Comma => ",",
+            Self::Bang => "!",
+            Self::DoubleEquals => "==",
+            Self::NotEquals => "!=",
+            Self::Less => "<",
+            Self::Greater => ">",
+            Self::LessEquals => "<=",
+            Self::GreaterEquals => ">=",
+            Self::PlusEquals => "+=",
+            Self::MinusEquals => "-=",
+            Self::StarEquals => "*=",
+            Self::SlashEquals => "/=",
+            Self::AndAnd => "&&",
+            Self::OrOr => "||",
+            Self::Begin
+                | Self::Var
+                | Self::Return
+                | Self::Func
+                | Self::True
+                | Self::False
+                | Self::If
+                | Self::Then
+                | Self::Else
+                | Self::While
+                | Self::For
+                | Self::In
+                | Self::Const
+                | Self::Break
+                | Self::Continue => self.keyword().unwrap(),
+        };
+        f.write_str(text)
+    }
+}
+
+/// A range of byte offsets into the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// There's no source to check a loaded span against, but it can't end before it starts
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Span {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename = "Span")]
+        struct Fields {
+            start: usize,
+            end: usize,
+        }
+
+        let Fields { start, end } = Fields::deserialize(deserializer)?;
+        if start > end {
+            return Err(serde::de::Error::custom(format!("span starts at byte {start}, after it ends at byte {end}")));
+        }
+        Ok(Span { start, end })
+    }
+}
+
+#[derive(Debug)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    /// 1-based line of the error
+    pub line: usize,
+    /// 1-based column (in chars) of the error
+    pub col: usize,
+}
+
+#[derive(Debug)]
+pub enum LexErrorKind {
+    /// A character that doesn't start any token
+    UnexpectedChar(char),
+    /// A `/*` with no matching `*/`
+    UnterminatedComment,
+    /// A `"` with no matching `"`
+    UnterminatedString,
+    /// A `\` in a string followed by something that isn't a known escape
+    InvalidEscape(char),
+    /// A `${` in a string with no matching `}`
+    UnterminatedInterpolation,
+    /// A digit that's too big for the radix of an int literal, like the 2 in `0b12`
+    InvalidDigit {
+        digit: char,
+        radix: u32,
+    },
+    /// A radix prefix like `0x` with no digits after it
+    MissingDigits,
+    /// A `_` digit separator at the start or end of a number, or next to another `_`
+    MisplacedSeparator,
+    /// `${}` interpolations nested inside each other more than `ast::DEFAULT_MAX_DEPTH` deep
+    NestingTooDeep,
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            LexErrorKind::UnexpectedChar(ch) => write!(f, "unexpected character {ch:?}")?,
+            LexErrorKind::UnterminatedComment => write!(f, "unterminated block comment")?,
+            LexErrorKind::UnterminatedString => write!(f, "unterminated string literal")?,
+            LexErrorKind::InvalidEscape(ch) => write!(f, "invalid escape sequence \\{ch}")?,
+            LexErrorKind::UnterminatedInterpolation => write!(f, "unterminated ${{}} in string literal")?,
+            LexErrorKind::InvalidDigit { digit, radix } => write!(f, "invalid digit {digit:?} in base {radix} literal")?,
+            LexErrorKind::MissingDigits => write!(f, "missing digits after radix prefix")?,
+            LexErrorKind::MisplacedSeparator => write!(f, "misplaced _ digit separator")?,
+            LexErrorKind::NestingTooDeep => write!(f, "string interpolations nested too deeply")?,
+        }
+        write!(f, " at line {}, column {}", self.line, self.col)
+    }
+}
+
+/// Convert a byte offset into `src` to a 1-based (line, column) pair
+pub fn line_col(src: &str, idx: usize) -> (usize, usize) {
+    let before = &src[..idx];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let col = before[line_start..].chars().count() + 1;
+    (line, col)
+}
+
+/// Resolve the escape sequences in the contents of a `Token::StringLit`
+pub fn unescape(raw: &str) -> String {
+    let mut s = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            // the lexer already rejected invalid escapes
+            s.push(match chars.next().unwrap() {
+                'n' => '\n',
+                't' => '\t',
+                other => other,
+            });
+        } else {
+            s.push(ch);
+        }
+    }
+    s
+}
+
+/// Split the text of an int literal into its radix and its digits, based on its prefix
+pub fn split_radix(text: &str) -> (u32, &str) {
+    match text.get(..2) {
+        Some("0x") => (16, &text[2..]),
+        Some("0o") => (8, &text[2..]),
+        Some("0b") => (2, &text[2..]),
+        _ => (10, text),
+    }
 }
 
-pub fn lex_tokens(src: &str) -> impl Iterator<Item=Token> {
+/// Lex `src` into tokens paired with their spans, stopping after the first error
+pub fn lex_tokens(src: &str) -> impl Iterator<Item=Result<(Token<'_>, Span), LexError>> {
     TokenStream {
         src,
         idx: 0,
+        depth: 0,
     }
 }
 
 struct TokenStream<'a> {
     src: &'a str,
     idx: usize,
+    /// How many `${}` interpolations the lexer is inside of
+    depth: usize,
 }
 
 impl<'a> TokenStream<'a> {
@@ -42,7 +292,11 @@ impl<'a> TokenStream<'a> {
     }
 
     fn eat_while<P: Fn(char) -> bool>(&mut self, predicate: P) -> Option<&'a str> {
-        let nmatching = self.tail().chars().take_while(|&c| predicate(c)).count();
+        let nmatching: usize = self.tail()
+            .chars()
+            .take_while(|&c| predicate(c))
+            .map(char::len_utf8)
+            .sum();
 
         if nmatching == 0 {
             None
@@ -57,22 +311,235 @@ impl<'a> TokenStream<'a> {
         self.eat_while(|ch| ch.is_ascii_whitespace());
     }
 
+    fn error(&self, kind: LexErrorKind, idx: usize) -> LexError {
+        let (line, col) = line_col(self.src, idx);
+        LexError { kind, line, col }
+    }
+
+    /// Skip a `//` comment, up to (but not including) the newline.
+    /// Returns whether there was a comment.
+    fn consume_line_comment(&mut self) -> bool {
+        if !self.tail().starts_with("//") {
+            return false;
+        }
+
+        self.idx += self.tail().find('\n').unwrap_or(self.tail().len());
+        true
+    }
+
+    /// Skip a `/* */` comment. Block comments nest, so `/* a /* b */ c */`
+    /// is a single comment. Returns whether there was a comment.
+    fn consume_block_comment(&mut self) -> Result<bool, LexError> {
+        if !self.tail().starts_with("/*") {
+            return Ok(false);
+        }
+
+        let start = self.idx;
+        self.idx += 2;
+        let mut depth = 1;
+        while depth > 0 {
+            if self.tail().starts_with("/*") {
+                depth += 1;
+                self.idx += 2;
+            } else if self.tail().starts_with("*/") {
+                depth -= 1;
+                self.idx += 2;
+            } else if let Some(ch) = self.peek() {
+                self.idx += ch.len_utf8();
+            } else {
+                return Err(self.error(LexErrorKind::UnterminatedComment, start));
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Skip any whitespace and comments
+    fn consume_trivia(&mut self) -> Result<(), LexError> {
+        loop {
+            self.consume_whitespace();
+            if !self.consume_line_comment() && !self.consume_block_comment()? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn lex_next(&mut self) -> Result<Option<(Token<'a>, Span)>, LexError> {
+        self.consume_trivia()?;
+        let start = self.idx;
+
+        let lexers = [
+            TokenStream::lex_bareword,
+            // must come before lex_onechar_symbol:
+            TokenStream::lex_multichar_symbol,
+            TokenStream::lex_onechar_symbol,
+        ];
+
+        if self.eof() {
+            return Ok(None);
+        }
+
+        if let Some(token) = self.lex_string()? {
+            return Ok(Some((token, Span { start, end: self.idx })));
+        }
+        if let Some(token) = self.lex_number()? {
+            return Ok(Some((token, Span { start, end: self.idx })));
+        }
+
+        for f in lexers {
+            if let Some(token) = f(self) {
+                return Ok(Some((token, Span { start, end: self.idx })));
+            }
+        }
+
+        Err(self.error(LexErrorKind::UnexpectedChar(self.peek().unwrap()), start))
+    }
+
+    /// Lex a keyword or identifier. These start with a letter or `_`,
+    /// followed by any number of letters, digits, or `_`s.
     fn lex_bareword(&mut self) -> Option<Token<'a>> {
-        let word = self.eat_while(char::is_alphabetic)?;
+        if !self.peek().is_some_and(|ch| ch.is_alphabetic() || ch == '_') {
+            return None;
+        }
+        let word = self.eat_while(|ch| ch.is_alphanumeric() || ch == '_')?;
 
         Some(
-            match word {
-                "begin" => Token::Begin,
-                "var" => Token::Var,
-                "return" => Token::Return,
-                "func" => Token::Func,
-                _ => Token::Ident(word),
-            }
+            KEYWORDS.iter()
+                .find(|(kw, _)| *kw == word)
+                .map_or(Token::Ident(word), |(_, tkn)| tkn.clone())
         )
     }
 
-    fn lex_integer(&mut self) -> Option<Token<'a>> {
-        self.eat_while(|ch| ch.is_ascii_digit()).map(Token::Integer)
+    /// Eat a run of digits, which can have single `_`s between them as separators
+    fn eat_digits(&mut self, is_digit: impl Fn(char) -> bool) -> Result<Option<&'a str>, LexError> {
+        let start = self.idx;
+        let Some(digits) = self.eat_while(|ch| is_digit(ch) || ch == '_') else {
+            return Ok(None);
+        };
+
+        let misplaced = if digits.starts_with('_') {
+            Some(0)
+        } else {
+            digits.find("__").or(digits.ends_with('_').then(|| digits.len() - 1))
+        };
+        if let Some(i) = misplaced {
+            return Err(self.error(LexErrorKind::MisplacedSeparator, start + i));
+        }
+
+        Ok(Some(digits))
+    }
+
+    /// Lex an int, or a float of the form `123.456`. Both sides of the `.`
+    /// must be present for a float, so `1.` and `.5` aren't floats.
+    fn lex_number(&mut self) -> Result<Option<Token<'a>>, LexError> {
+        if !self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+            return Ok(None);
+        }
+        let start = self.idx;
+        let tail = self.tail();
+
+        let (radix, _) = split_radix(tail);
+        if radix != 10 {
+            self.idx += 2;
+            // take all alphanumerics so that bad digits are an error instead of the start of the next token
+            let digits = self.eat_digits(|ch| ch.is_ascii_alphanumeric())?
+                .ok_or_else(|| self.error(LexErrorKind::MissingDigits, start))?;
+            if let Some((i, digit)) = digits.char_indices().find(|&(_, ch)| ch != '_' && !ch.is_digit(radix)) {
+                return Err(self.error(LexErrorKind::InvalidDigit { digit, radix }, start + 2 + i));
+            }
+            return Ok(Some(Token::Integer(&tail[..self.idx - start])));
+        }
+
+        self.eat_digits(|ch| ch.is_ascii_digit())?;
+        let rest = self.tail();
+        if rest.starts_with('.') && rest[1..].starts_with(|ch: char| ch.is_ascii_digit()) {
+            self.idx += 1;
+            self.eat_digits(|ch| ch.is_ascii_digit())?;
+            Ok(Some(Token::Float(&tail[..self.idx - start])))
+        } else {
+            Ok(Some(Token::Integer(&tail[..self.idx - start])))
+        }
+    }
+
+    fn lex_multichar_symbol(&mut self) -> Option<Token<'a>> {
+        let symbols = [
+            ("==", Token::DoubleEquals),
+            ("**", Token::StarStar),
+            ("!=", Token::NotEquals),
+            ("<=", Token::LessEquals),
+            (">=", Token::GreaterEquals),
+            ("+=", Token::PlusEquals),
+            ("-=", Token::MinusEquals),
+            ("*=", Token::StarEquals),
+            ("/=", Token::SlashEquals),
+            ("&&", Token::AndAnd),
+            ("||", Token::OrOr),
+            ("->", Token::Arrow),
+            ("#{", Token::HashLeftBrace),
+            ("..=", Token::DotDotEquals),
+            ("..", Token::DotDot),
+        ];
+
+        for (sym, tkn) in symbols {
+            if self.tail().starts_with(sym) {
+                self.idx += sym.len();
+                return Some(tkn);
+            }
+        }
+
+        None
+    }
+
+    /// Lex a string literal. Any `${}` interpolations in it are lexed too, to find
+    /// where they end, but they're left in the token's raw text for the parser.
+    fn lex_string(&mut self) -> Result<Option<Token<'a>>, LexError> {
+        if self.peek() != Some('"') {
+            return Ok(None);
+        }
+
+        let start = self.idx;
+        self.idx += 1;
+        while let Some(ch) = self.peek() {
+            let ch_start = self.idx;
+            self.idx += ch.len_utf8();
+            match ch {
+                '"' => return Ok(Some(Token::StringLit(&self.src[start + 1..ch_start]))),
+                '\\' => match self.peek() {
+                    Some('n' | 't' | '\\' | '"' | '$') => self.idx += 1,
+                    Some(other) => return Err(self.error(LexErrorKind::InvalidEscape(other), ch_start)),
+                    None => break,
+                },
+                '$' if self.peek() == Some('{') => {
+                    self.idx += 1;
+                    self.skip_interpolation(ch_start)?;
+                },
+                _ => {},
+            }
+        }
+
+        Err(self.error(LexErrorKind::UnterminatedString, start))
+    }
+
+    /// Skip the tokens of a `${}` interpolation, up to and including its closing `}`
+    fn skip_interpolation(&mut self, start: usize) -> Result<(), LexError> {
+        // lexing an interpolation recurses, so limit nesting like the parser does
+        if self.depth == crate::ast::DEFAULT_MAX_DEPTH {
+            return Err(self.error(LexErrorKind::NestingTooDeep, start));
+        }
+        self.depth += 1;
+        let mut braces = 0;
+        loop {
+            match self.lex_next()? {
+                Some((Token::LeftBrace | Token::HashLeftBrace, _)) => braces += 1,
+                Some((Token::RightBrace, _)) if braces == 0 => {
+                    self.depth -= 1;
+                    return Ok(());
+                },
+                Some((Token::RightBrace, _)) => braces -= 1,
+                Some(_) => {},
+                None => return Err(self.error(LexErrorKind::UnterminatedInterpolation, start)),
+            }
+        }
     }
 
     fn lex_onechar_symbol(&mut self) -> Option<Token<'a>> {
@@ -82,9 +549,19 @@ impl<'a> TokenStream<'a> {
             '=' => Token::Equals,
             '(' => Token::LeftParen,
             ')' => Token::RightParen,
+            '[' => Token::LeftBracket,
+            ']' => Token::RightBracket,
             '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
             ';' => Token::Semicolon,
+            ':' => Token::Colon,
             ',' => Token::Comma,
+            '!' => Token::Bang,
+            '<' => Token::Less,
+            '>' => Token::Greater,
             _ => return None,
         };
 
@@ -95,27 +572,130 @@ impl<'a> TokenStream<'a> {
 }
 
 impl<'a> Iterator for TokenStream<'a> {
-    type Item = Token<'a>;
+    type Item = Result<(Token<'a>, Span), LexError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.consume_whitespace();
+        let res = self.lex_next();
+        if res.is_err() {
+            // don't keep lexing after an error:
+            self.idx = self.src.len();
+        }
+        res.transpose()
+    }
+}
 
-        let lexers = [
-            TokenStream::lex_bareword,
-            TokenStream::lex_onechar_symbol,
-            TokenStream::lex_integer,
-        ];
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if self.eof() {
-            return None;
-        }
+    #[test]
+    fn tokens_display_as_written() {
+        let src = "begin { x += \"a${b}\" ..= #{ 0x1F; <= } }";
+        let written: Vec<String> = lex_tokens(src).map(|res| res.unwrap().0.to_string()).collect();
+        assert_eq!(written, ["begin", "{", "x", "+=", "\"a${b}\"", "..=", "#{", "0x1F", ";", "<=", "}", "}"]);
+    }
 
-        for f in lexers {
-            if let Some(token) = f(self) {
-                return Some(token);
-            }
+    fn tokens(src: &str) -> Vec<Token<'_>> {
+        lex_tokens(src).map(|res| res.unwrap().0).collect()
+    }
+
+    fn error(src: &str) -> LexError {
+        lex_tokens(src).find_map(Result::err).expect("should fail to lex")
+    }
+
+    #[test]
+    fn lexes_a_snippet() {
+        assert_eq!(
+            tokens("var x = f(1, 2.5) >= -3;"),
+            [
+                Token::Var,
+                Token::Ident("x"),
+                Token::Equals,
+                Token::Ident("f"),
+                Token::LeftParen,
+                Token::Integer("1"),
+                Token::Comma,
+                Token::Float("2.5"),
+                Token::RightParen,
+                Token::GreaterEquals,
+                Token::Minus,
+                Token::Integer("3"),
+                Token::Semicolon,
+            ],
+        );
+    }
+
+    #[test]
+    fn spans_are_byte_offsets() {
+        let spans: Vec<Span> = lex_tokens("a é\n").map(|res| res.unwrap().1).collect::<Vec<_>>();
+        assert_eq!(spans[0], Span { start: 0, end: 1 });
+        assert_eq!(spans[1], Span { start: 2, end: 4 });
+    }
+
+    #[test]
+    fn identifiers_can_have_digits_and_underscores() {
+        assert_eq!(tokens("my_var2 _x"), [Token::Ident("my_var2"), Token::Ident("_x")]);
+        assert_eq!(tokens("2foo"), [Token::Integer("2"), Token::Ident("foo")]);
+        assert_eq!(tokens("iffy if"), [Token::Ident("iffy"), Token::If]);
+    }
+
+    #[test]
+    fn errors_have_line_and_column() {
+        let e = error("begin {\n  var x = 1;\n  var y @ 2;\n}");
+        assert!(matches!(e.kind, LexErrorKind::UnexpectedChar('@')));
+        assert_eq!((e.line, e.col), (3, 9));
+        // columns count chars, not bytes
+        assert_eq!(error("\"é\" @").col, 5);
+    }
+
+    #[test]
+    fn line_comments_are_skipped() {
+        assert_eq!(tokens("a; // b; c\n// d\ne"), [Token::Ident("a"), Token::Semicolon, Token::Ident("e")]);
+        assert_eq!(tokens("a // no newline at the end"), [Token::Ident("a")]);
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        assert_eq!(tokens("a /* b /* c */ d */ e"), [Token::Ident("a"), Token::Ident("e")]);
+        assert_eq!(tokens("a/**/b"), [Token::Ident("a"), Token::Ident("b")]);
+        let e = error("a\n /* b /* c */ d");
+        assert!(matches!(e.kind, LexErrorKind::UnterminatedComment));
+        assert_eq!((e.line, e.col), (2, 2));
+    }
+
+    #[test]
+    fn string_literals() {
+        assert_eq!(tokens(r#""hi\n" "" "#), [Token::StringLit(r"hi\n"), Token::StringLit("")]);
+        assert_eq!(unescape(r#"hi\n\t\"\\\$"#), "hi\n\t\"\\$");
+        assert!(matches!(error(r#""abc"#).kind, LexErrorKind::UnterminatedString));
+        assert!(matches!(error(r#""a\qb""#).kind, LexErrorKind::InvalidEscape('q')));
+    }
+
+    #[test]
+    fn interpolations_are_part_of_the_string() {
+        assert_eq!(tokens(r#""a${ "}" }b""#), [Token::StringLit(r#"a${ "}" }b"#)]);
+        assert!(matches!(error(r#""a${b"#).kind, LexErrorKind::UnterminatedInterpolation));
+    }
+
+    #[test]
+    fn int_literals_with_radixes() {
+        assert_eq!(tokens("0xFF 0b101 0o17"), [Token::Integer("0xFF"), Token::Integer("0b101"), Token::Integer("0o17")]);
+        assert_eq!(split_radix("0x1F"), (16, "1F"));
+        assert!(matches!(error("0b12").kind, LexErrorKind::InvalidDigit { digit: '2', radix: 2 }));
+        assert!(matches!(error("0x;").kind, LexErrorKind::MissingDigits));
+    }
+
+    #[test]
+    fn digit_separators() {
+        assert_eq!(tokens("1_000 0xFF_FF 1.5_0"), [Token::Integer("1_000"), Token::Integer("0xFF_FF"), Token::Float("1.5_0")]);
+        for misplaced in ["1__0", "1_", "0x_1", "1.5_"] {
+            assert!(matches!(error(misplaced).kind, LexErrorKind::MisplacedSeparator), "{misplaced}");
         }
+    }
 
-        panic!("Lexing error at idx {}", self.idx);
+    #[test]
+    fn a_dot_needs_digits_on_both_sides_for_a_float() {
+        assert_eq!(tokens("1..2"), [Token::Integer("1"), Token::DotDot, Token::Integer("2")]);
+        assert_eq!(tokens("1..=2"), [Token::Integer("1"), Token::DotDotEquals, Token::Integer("2")]);
     }
 }
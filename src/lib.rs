@@ -0,0 +1,177 @@
+pub mod lex;
+pub mod ast;
+pub mod interp;
+pub mod format;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod check;
+pub mod compile;
+pub mod vm;
+mod resolve;
+
+use std::collections::HashMap;
+
+/// Anything that can go wrong running a program
+#[derive(Debug)]
+pub enum Error<'a> {
+    Lex(lex::LexError),
+    Parse(ast::ParseError<'a>),
+    Program(interp::ProgramError),
+    /// Everything the checker found wrong, in the order it's in the source
+    Semantic(Vec<check::SemanticError>),
+    Runtime(interp::RuntimeError),
+    /// Loading an AST from JSON failed
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for Error<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Lex(e) => write!(f, "Lex error: {e}"),
+            Self::Parse(e) => write!(f, "Parse error: {e}"),
+            Self::Program(e) => write!(f, "Error: {e}"),
+            Self::Semantic(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i != 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "Error: {e}")?;
+                }
+                Ok(())
+            },
+            Self::Runtime(e) => write!(f, "Runtime error: {e}"),
+            #[cfg(feature = "serde")]
+            Self::Json(e) => write!(f, "JSON error: {e}"),
+        }
+    }
+}
+
+impl Error<'_> {
+    /// Where in the source the error is, if known. Lex errors carry their own line and column instead.
+    pub fn span(&self) -> Option<lex::Span> {
+        match self {
+            Self::Lex(_) => None,
+            #[cfg(feature = "serde")]
+            Self::Json(_) => None,
+            Self::Parse(e) => e.span(),
+            Self::Program(e) => e.span(),
+            Self::Semantic(errors) => errors.first().map(check::SemanticError::span),
+            Self::Runtime(e) => e.span(),
+        }
+    }
+}
+
+/// `msg` followed by a frame showing where `span` is in `src`: the line it
+/// starts on, with carets under it up to the end of that line, like
+///
+/// ```text
+/// Error: variable y is not defined (used at byte 16)
+///   --> line 2, column 9
+///   |
+/// 2 |     x = y;
+///   |         ^
+/// ```
+///
+/// A span that runs past the end of `src`, or splits a character, is moved
+/// back to the nearest character boundary in it.
+pub fn render_diagnostic(src: &str, span: lex::Span, msg: &str) -> String {
+    let start = floor_char_boundary(src, span.start);
+    let (line, col) = lex::line_col(src, start);
+    let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+    let text = src[line_start..line_end].trim_end_matches('\r');
+    // keep any tabs before the span, so the carets line up however wide they're shown
+    let indent: String = src[line_start..start].chars().map(|ch| if ch == '\t' { '\t' } else { ' ' }).collect();
+    let end = floor_char_boundary(src, span.end.clamp(start, line_end));
+    let carets = src[start..end].chars().count().max(1);
+
+    let gutter = " ".repeat(line.to_string().len());
+    format!(
+        "{msg}\n{gutter} --> line {line}, column {col}\n{gutter} |\n{line} | {text}\n{gutter} | {indent}{}",
+        "^".repeat(carets),
+    )
+}
+
+/// The last char boundary in `src` at or before `idx`
+fn floor_char_boundary(src: &str, idx: usize) -> usize {
+    let mut idx = idx.min(src.len());
+    while !src.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+/// Lex, parse, and execute the program in `src`, returning the final values
+/// of the variables in its `begin` block.
+///
+/// This needs about 2 MiB of stack, the default for spawned threads, which fits
+/// anything nested as deeply or chained as long as the parser allows. Calls get
+/// more stack as they need it.
+pub fn run_source(src: &str) -> Result<HashMap<String, interp::Value>, Error<'_>> {
+    let tokens = lex::lex_tokens(src)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::Lex)?;
+    let items = ast::parse_items(tokens.into_iter())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::Parse)?;
+    let program = interp::Program::from_items(items.into_iter())
+        .map_err(Error::Program)?;
+    check::check_program(&program).map_err(Error::Semantic)?;
+    program.execute()
+        .map(interp::Context::into_variables)
+        .map_err(Error::Runtime)
+}
+
+/// Like `run_source`, but for a program already parsed and saved with `json::ast_to_json`
+#[cfg(feature = "serde")]
+pub fn run_json(json: &str) -> Result<HashMap<String, interp::Value>, Error<'_>> {
+    let items = json::ast_from_json(json).map_err(Error::Json)?;
+    let program = interp::Program::from_items(items.into_iter())
+        .map_err(Error::Program)?;
+    check::check_program(&program).map_err(Error::Semantic)?;
+    program.execute()
+        .map(interp::Context::into_variables)
+        .map_err(Error::Runtime)
+}
+
+/// Generate a valid, runnable program whose begin block has `statements`
+/// statements in it, mixing declarations, arithmetic, calls, branches, and
+/// strings. For benchmarking on programs bigger than any real one.
+pub fn generate_source(statements: usize) -> String {
+    let mut src = String::from("func step(x: int) -> int {\n\treturn x * 3 % 7 + 1;\n}\n\nbegin {\n\tvar acc = 0;\n");
+    for i in 0..statements {
+        match i % 4 {
+            0 => src.push_str(&format!("\tvar v{i} = {i} + acc * 2;\n")),
+            1 => src.push_str(&format!("\tacc = step(acc) + {i};\n")),
+            2 => src.push_str(&format!("\tif acc > {i} {{\n\t\tacc -= 1;\n\t}} else {{\n\t\tacc += 2;\n\t}}\n")),
+            _ => src.push_str(&format!("\tvar s{i} = \"item ${{acc}}\";\n")),
+        }
+    }
+    src.push_str("}\n");
+    src
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostic_points_at_span() {
+        let rendered = render_diagnostic("begin {\n    x = y;\n}", lex::Span { start: 16, end: 17 }, "Error: oops");
+        assert_eq!(rendered, "Error: oops\n  --> line 2, column 9\n  |\n2 |     x = y;\n  |         ^");
+    }
+
+    #[test]
+    fn diagnostic_clamps_span_past_end() {
+        let rendered = render_diagnostic("begin { }", lex::Span { start: 99999, end: 99999 }, "Error: oops");
+        assert_eq!(rendered, "Error: oops\n  --> line 1, column 10\n  |\n1 | begin { }\n  |          ^");
+    }
+
+    #[test]
+    fn diagnostic_floors_span_to_char_boundary() {
+        // 'é' is bytes 9 and 10, so byte 10 is in the middle of it
+        let rendered = render_diagnostic("begin { \"é\" }", lex::Span { start: 10, end: 11 }, "Error: oops");
+        assert!(rendered.ends_with("1 | begin { \"é\" }\n  |          ^"), "{rendered}");
+    }
+}
@@ -1,9 +1,105 @@
-mod lex;
-mod ast;
-mod interp;
+use foolang::ast::{self, Interner, ReplInput};
+use foolang::interp::{Context, Program, Value};
+use foolang::{lex, Error};
+use std::io::{BufRead, IsTerminal, Write};
+use typed_arena::Arena;
 
 fn main() {
-    let tokens = lex::lex_tokens(include_str!("../example.foo"));
-    let items = ast::parse_items(tokens);
-    interp::Program::from_items(items).execute();
+    let mut args = std::env::args();
+    let argv0 = args.next().unwrap_or_else(|| "foolang".to_string());
+    let arg = args.next();
+    if args.next().is_some() {
+        eprintln!("Usage: {argv0} [file.foo | --repl]");
+        eprintln!("Reads the program from stdin if no file is given, or starts a REPL if stdin is a terminal");
+        std::process::exit(2);
+    }
+
+    if arg.as_deref() == Some("--repl") || (arg.is_none() && std::io::stdin().is_terminal()) {
+        repl();
+        return;
+    }
+
+    let path = arg;
+    let src = match &path {
+        Some(path) => std::fs::read_to_string(path),
+        None => std::io::read_to_string(std::io::stdin()),
+    };
+    let src = match src {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("Can't read {}: {e}", path.as_deref().unwrap_or("stdin"));
+            std::process::exit(1);
+        },
+    };
+
+    run(&src);
+}
+
+fn run(src: &str) {
+    if let Err(e) = foolang::run_source(src) {
+        match &e {
+            // each of the checker's errors gets a frame of its own
+            Error::Semantic(errors) => {
+                for error in errors {
+                    eprintln!("{}", foolang::render_diagnostic(src, error.span(), &format!("Error: {error}")));
+                }
+            },
+            _ => match e.span() {
+                Some(span) => eprintln!("{}", foolang::render_diagnostic(src, span, &e.to_string())),
+                None => eprintln!("{e}"),
+            },
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Read lines from stdin and run them until EOF, printing the value of each.
+/// Variables declared on one line are kept for the next ones.
+fn repl() {
+    // the context borrows from the program and from every line's AST, since
+    // closures and functions made on one line can be called on later ones, so
+    // the ASTs are kept in an arena that lives as long as the REPL does. They
+    // own their names, so each line's source can go once it's parsed.
+    let program = Program::empty();
+    let inputs = Arena::new();
+    let mut ctx = program.top_level_context();
+    let mut interner = Interner::default();
+    let mut stdin = std::io::stdin().lock();
+
+    loop {
+        print!("> ");
+        std::io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        match stdin.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {},
+            Err(e) => {
+                eprintln!("Can't read stdin: {e}");
+                std::process::exit(1);
+            },
+        }
+
+        match eval_repl_line(&mut ctx, &inputs, &mut interner, &line) {
+            Ok(Some(Value::Unit) | None) => {},
+            Ok(Some(value)) => println!("{value}"),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+
+    // end the line with the last prompt on it
+    println!();
+}
+
+fn eval_repl_line<'a, 'l>(
+    ctx: &mut Context<'a>,
+    inputs: &'a Arena<ReplInput<'a>>,
+    interner: &mut Interner,
+    line: &'l str,
+) -> Result<Option<Value>, Error<'l>> {
+    let tokens = lex::lex_tokens(line)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::Lex)?;
+    let input = ast::parse_repl_input(tokens.into_iter(), interner).map_err(Error::Parse)?;
+    ctx.eval_repl_input(inputs.alloc(input.to_owned())).map_err(Error::Runtime)
 }
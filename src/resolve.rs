@@ -0,0 +1,336 @@
+//! Working out where each variable lives before running, so the interpreter
+//! can index straight into a `Vec` instead of looking names up.
+//!
+//! Each function call, and the begin block, gets a frame: a `Vec` of the
+//! locals currently in scope, in the order they were declared. Scopes nest,
+//! so whatever is in scope at some point is always the frame up to some
+//! length, and a local's slot is just how many locals were already in scope
+//! where it's declared. A function's args are its first slots.
+//!
+//! Leaving a scope truncates the frame back to how long it was on entering, so
+//! sibling scopes reuse the same slots, and a declaration always pushes onto the
+//! end of the frame. An inner declaration shadowing an outer one gets a slot of
+//! its own. A name that isn't a local in scope refers to a global variable if
+//! there is one, and otherwise to the function or builtin of that name.
+//!
+//! Lambdas and functions defined in blocks get frames of their own, which are
+//! nested in the frame of the function they're in, and so on out to a top-level
+//! function or the begin block. A name that isn't one of their own locals can be
+//! a local of an enclosing frame, as far as it's in scope where the lambda or
+//! function is, which resolves to a `Slot::Outer`. Functions defined in a block
+//! are declared before anything else in it, so they're in scope all through it.
+//! They're the first slots of the block, so they exist as soon as it starts.
+//!
+//! Declaring a name twice in the same scope resolves to the first declaration's
+//! slot. That slot is below the frame's length by the time the second declaration
+//! runs, which is how the interpreter knows to fail with a redeclaration error.
+
+use crate::ast::{Expr, Ident, Lambda, Slot, Statement, StringPart};
+use crate::interp::{Builtin, IdentSet};
+use std::cell::Cell;
+
+/// The names that are in scope everywhere in a program
+#[derive(Debug, Default)]
+pub(crate) struct GlobalNames<'a> {
+    pub(crate) vars: IdentSet<'a>,
+    /// User functions. Builtins are always there, so they aren't included.
+    pub(crate) functions: IdentSet<'a>,
+}
+
+/// A function defined in a block whose body is being resolved, and whether it
+/// uses any of the locals its block declares, other than functions
+struct BlockLocalUse {
+    /// The level its block's frame would have as a `Slot::Outer`
+    level: usize,
+    /// The slot of the block's first local that isn't a function
+    first_slot: usize,
+    used: Cell<bool>,
+}
+
+pub(crate) struct Resolver<'r, 'a> {
+    globals: &'r GlobalNames<'a>,
+    /// The locals in scope, each at the index of its slot
+    locals: Vec<Ident<'a>>,
+    /// The locals in scope of each frame that this one is nested in, outermost first
+    enclosing: Vec<Vec<Ident<'a>>>,
+    /// One for each function defined in a block whose body is being resolved, innermost last
+    block_local_uses: Vec<BlockLocalUse>,
+    /// Where in `locals` each scope starts, innermost last
+    scope_starts: Vec<usize>,
+    /// The function being resolved, if it's a function body, for finding tail calls
+    func_name: Option<Ident<'a>>,
+    /// How many block expressions the resolver is inside of
+    block_exprs: usize,
+}
+
+impl<'r, 'a> Resolver<'r, 'a> {
+    /// A resolver for a frame that starts out with `locals` in its outermost scope
+    pub(crate) fn new(globals: &'r GlobalNames<'a>, locals: Vec<Ident<'a>>) -> Self {
+        Self {
+            globals,
+            locals,
+            enclosing: Vec::new(),
+            block_local_uses: Vec::new(),
+            scope_starts: vec![0],
+            func_name: None,
+            block_exprs: 0,
+        }
+    }
+
+    /// A resolver for the body of the function `func_name`
+    pub(crate) fn for_function(globals: &'r GlobalNames<'a>, func_name: Ident<'a>, arg_names: Vec<Ident<'a>>) -> Self {
+        Self { func_name: Some(func_name), ..Self::new(globals, arg_names) }
+    }
+
+    /// A resolver for the body of a lambda or a function defined in a block that's
+    /// in the code being resolved, whose frame starts with its args
+    fn for_nested(&self, arg_names: &[Ident<'a>]) -> Resolver<'r, 'a> {
+        let mut enclosing = self.enclosing.clone();
+        enclosing.push(self.locals.clone());
+        let block_local_uses = self.block_local_uses.iter()
+            .map(|uses| BlockLocalUse { used: Cell::new(false), ..*uses })
+            .collect();
+        Resolver { enclosing, block_local_uses, ..Resolver::new(self.globals, arg_names.to_vec()) }
+    }
+
+    /// Where the variable `name` is. A name that isn't a local is assumed to be a
+    /// global, which fails at runtime if there's no such global.
+    fn lookup(&self, name: &Ident) -> Slot {
+        if let Some(slot) = self.locals.iter().rposition(|local| local == name) {
+            return Slot::Local(slot);
+        }
+        for (level, locals) in self.enclosing.iter().enumerate().rev() {
+            if let Some(index) = locals.iter().rposition(|local| local == name) {
+                for uses in &self.block_local_uses {
+                    if uses.level == level && index >= uses.first_slot {
+                        uses.used.set(true);
+                    }
+                }
+                return Slot::Outer { level, index };
+            }
+        }
+        Slot::Global
+    }
+
+    /// Like `lookup`, but for a name used as a value, which can also be a function.
+    /// The callee of a call is assumed to be a function if it isn't a variable.
+    fn lookup_value(&self, name: &Ident, is_callee: bool) -> Slot {
+        match self.lookup(name) {
+            Slot::Global if !self.globals.vars.contains(name)
+                && (is_callee || self.globals.functions.contains(name) || Builtin::from_name(name.name()).is_some()) => {
+                Slot::Function
+            },
+            slot => slot,
+        }
+    }
+
+    fn declare(&mut self, name: Ident<'a>) -> Slot {
+        let scope_start = *self.scope_starts.last().unwrap();
+        if let Some(i) = self.locals[scope_start..].iter().position(|local| *local == name) {
+            return Slot::Local(scope_start + i);
+        }
+        self.locals.push(name);
+        Slot::Local(self.locals.len() - 1)
+    }
+
+    fn with_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scope_starts.push(self.locals.len());
+        f(self);
+        let scope_start = self.scope_starts.pop().unwrap();
+        self.locals.truncate(scope_start);
+    }
+
+    /// Resolve the target of an assignment. Its indexes are resolved like any other
+    /// expression, but the variable they index is assigned to, so it's never a function.
+    fn resolve_assign_target(&mut self, mut target: &mut Expr<'a>) {
+        loop {
+            match target {
+                Expr::Index { array, index } => {
+                    self.resolve_expr(index);
+                    target = array;
+                },
+                Expr::VarRef { variable, slot, .. } => {
+                    *slot = self.lookup(variable);
+                    return;
+                },
+                other => unreachable!("{other:?} can't be assigned to"),
+            }
+        }
+    }
+
+    pub(crate) fn resolve_block(&mut self, stmts: &mut [Statement<'a>]) {
+        // functions defined in the block are in scope all through it
+        for stmt in stmts.iter_mut() {
+            if let Statement::FuncDef { name, slot, .. } = stmt {
+                *slot = self.declare(name.clone());
+            }
+        }
+        let first_slot = self.locals.len();
+        for stmt in stmts {
+            match stmt {
+                Statement::FuncDef { func, defined_on_entry, .. } => {
+                    let level = self.enclosing.len();
+                    self.block_local_uses.push(BlockLocalUse { level, first_slot, used: Cell::new(false) });
+                    self.resolve_lambda(func);
+                    *defined_on_entry = !self.block_local_uses.pop().unwrap().used.get();
+                },
+                stmt => self.resolve_statement(stmt),
+            }
+        }
+    }
+
+    /// Resolve the body of a lambda or function defined in a block, which has a
+    /// frame of its own, so returns in it return from it
+    fn resolve_lambda(&mut self, lambda: &mut Lambda<'a>) {
+        let mut resolver = self.for_nested(&lambda.arg_names);
+        resolver.resolve_block(&mut lambda.body);
+        for (uses, nested_uses) in self.block_local_uses.iter().zip(&resolver.block_local_uses) {
+            if nested_uses.used.get() {
+                uses.used.set(true);
+            }
+        }
+    }
+
+    fn resolve_scoped_block(&mut self, stmts: &mut [Statement<'a>]) {
+        self.with_scope(|resolver| resolver.resolve_block(stmts));
+    }
+
+    fn resolve_statement(&mut self, stmt: &mut Statement<'a>) {
+        match stmt {
+            Statement::VarDeclaration { variable, value, slot, .. } => {
+                // a variable isn't in scope in its own initializer
+                self.resolve_expr(value);
+                *slot = self.declare(variable.clone());
+            },
+            Statement::MultiVarDeclaration { declarations } => self.resolve_block(declarations),
+            Statement::Return { value, is_tail_call } => {
+                if let Some(value) = value {
+                    self.resolve_expr(value);
+                }
+                *is_tail_call = self.block_exprs == 0 && matches!(
+                    value,
+                    Some(Expr::FuncCall { callee, .. }) if matches!(
+                        **callee,
+                        Expr::VarRef { ref variable, slot: Slot::Function, .. } if self.func_name.as_ref() == Some(variable),
+                    ),
+                );
+            },
+            Statement::Break { .. } | Statement::Continue { .. } => {},
+            Statement::ExprStmt { expr } => self.resolve_expr(expr),
+            Statement::FuncDef { .. } => unreachable!("functions defined in a block are resolved with the block"),
+            Statement::If { condition, then_block, else_block } => {
+                self.resolve_expr(condition);
+                self.resolve_scoped_block(then_block);
+                if let Some(else_block) = else_block {
+                    self.resolve_scoped_block(else_block);
+                }
+            },
+            Statement::While { condition, body } => {
+                self.resolve_expr(condition);
+                self.resolve_scoped_block(body);
+            },
+            Statement::For { init, condition, step, body } => {
+                self.with_scope(|resolver| {
+                    resolver.resolve_statement(init);
+                    resolver.resolve_expr(condition);
+                    resolver.resolve_statement(step);
+                    resolver.resolve_scoped_block(body);
+                });
+            },
+            Statement::ForEach { variable, iterable, body, .. } => {
+                self.resolve_expr(iterable);
+                self.with_scope(|resolver| {
+                    resolver.declare(variable.clone());
+                    resolver.resolve_scoped_block(body);
+                });
+            },
+        }
+    }
+
+    pub(crate) fn resolve_expr(&mut self, expr: &mut Expr<'a>) {
+        // walk chains down the lhs, like `Context::reduce_binop_chain`. Nothing an
+        // operand declares outlives it, so operands can be resolved in any order.
+        let mut expr = expr;
+        while let Expr::Add { lhs, rhs }
+            | Expr::Sub { lhs, rhs }
+            | Expr::Mul { lhs, rhs }
+            | Expr::Div { lhs, rhs }
+            | Expr::Mod { lhs, rhs }
+            | Expr::Pow { lhs, rhs }
+            | Expr::Eq { lhs, rhs }
+            | Expr::NotEq { lhs, rhs }
+            | Expr::Less { lhs, rhs }
+            | Expr::Greater { lhs, rhs }
+            | Expr::LessEq { lhs, rhs }
+            | Expr::GreaterEq { lhs, rhs }
+            | Expr::And { lhs, rhs }
+            | Expr::Or { lhs, rhs }
+            | Expr::Range { lhs, rhs }
+            | Expr::RangeInclusive { lhs, rhs } = expr
+        {
+            self.resolve_expr(rhs);
+            expr = lhs;
+        }
+
+        match expr {
+            Expr::IntLit { .. }
+                | Expr::FloatLit { .. }
+                | Expr::BoolLit { .. }
+                | Expr::StringLit { .. } => {},
+            Expr::VarRef { variable, slot, .. } => *slot = self.lookup_value(variable, false),
+            Expr::Neg { operand } | Expr::Not { operand } => self.resolve_expr(operand),
+            Expr::Block { body, value } => {
+                self.block_exprs += 1;
+                self.with_scope(|resolver| {
+                    resolver.resolve_block(body);
+                    if let Some(value) = value {
+                        resolver.resolve_expr(value);
+                    }
+                });
+                self.block_exprs -= 1;
+            },
+            Expr::InterpolatedString { parts } => {
+                for part in parts {
+                    if let StringPart::Expr(expr) = part {
+                        self.resolve_expr(expr);
+                    }
+                }
+            },
+            Expr::ArrayLit { elements } => {
+                for elem in elements {
+                    self.resolve_expr(elem);
+                }
+            },
+            Expr::MapLit { entries } => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            },
+            Expr::Lambda(lambda) => self.resolve_lambda(lambda),
+            Expr::Assign { target, value, .. } => {
+                self.resolve_assign_target(target);
+                self.resolve_expr(value);
+            },
+            Expr::FuncCall { callee, args, .. } => {
+                match &mut **callee {
+                    Expr::VarRef { variable, slot, .. } => *slot = self.lookup_value(variable, true),
+                    callee => self.resolve_expr(callee),
+                }
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            },
+            Expr::Index { array, index } => {
+                self.resolve_expr(array);
+                self.resolve_expr(index);
+            },
+            Expr::IfExpr { condition, then_expr, else_expr } => {
+                self.resolve_expr(condition);
+                self.resolve_expr(then_expr);
+                self.resolve_expr(else_expr);
+            },
+            _ => unreachable!("binary operators are handled above"),
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Running bytecode from `compile`. Runtime errors are the same as the
+//! interpreter gives for the same program.
+
+use crate::compile::{Bytecode, Op};
+use crate::interp::{self, Output, RuntimeError, Value, MAX_CALL_DEPTH};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::Write;
+
+#[derive(Debug)]
+struct Local {
+    value: Value,
+    /// Index into `Bytecode::local_names`
+    name: usize,
+    is_const: bool,
+}
+
+#[derive(Debug)]
+struct Global {
+    value: Value,
+    is_const: bool,
+}
+
+/// One call in progress
+#[derive(Debug)]
+struct Frame {
+    /// Index into `Bytecode::functions`, or `None` for the begin block
+    func: Option<usize>,
+    /// Index of the next op to run
+    pc: usize,
+    locals: Vec<Local>,
+}
+
+#[derive(Debug)]
+pub struct Vm<'b> {
+    bytecode: &'b Bytecode,
+    stack: Vec<Value>,
+    /// Innermost call is last. The first frame is the begin block.
+    frames: Vec<Frame>,
+    /// Indexed like `Bytecode::global_names`, and `None` until initialized
+    globals: Vec<Option<Global>>,
+    output: Output<'b>,
+}
+
+impl<'b> Vm<'b> {
+    pub fn new(bytecode: &'b Bytecode) -> Self {
+        Self {
+            bytecode,
+            stack: Vec::new(),
+            frames: vec![Frame { func: None, pc: 0, locals: Vec::new() }],
+            globals: bytecode.global_names.iter().map(|_| None).collect(),
+            output: Output::Stdout,
+        }
+    }
+
+    /// Make `print` write to `output` instead of stdout, like `Program::with_output`
+    pub fn with_output(mut self, output: &'b mut dyn Write) -> Self {
+        self.output = Output::Writer(RefCell::new(output));
+        self
+    }
+
+    /// Run the program to the end of the begin block, returning the final values
+    /// of the globals and of the begin block's variables, like `run_source` does
+    pub fn run(mut self) -> Result<HashMap<String, Value>, RuntimeError> {
+        let bytecode = self.bytecode;
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let code = match frame.func {
+                Some(func) => &bytecode.functions[func].code,
+                None => &bytecode.begin,
+            };
+            let op = &code[frame.pc];
+            frame.pc += 1;
+
+            match op {
+                Op::Push(value) => self.stack.push(value.clone()),
+                Op::Pop => {
+                    self.pop();
+                },
+                Op::LoadLocal(slot) => {
+                    let value = self.locals()[*slot].value.clone();
+                    self.stack.push(value);
+                },
+                Op::DeclareLocal { name, is_const } => {
+                    let value = self.pop();
+                    self.locals().push(Local { value, name: *name, is_const: *is_const });
+                },
+                Op::StoreLocal(slot) => {
+                    let value = self.pop();
+                    let local = &mut self.locals()[*slot];
+                    if local.is_const {
+                        return Err(RuntimeError::AssignToConst(bytecode.local_names[local.name].clone()));
+                    }
+                    local.value = value;
+                },
+                Op::Redeclaration { name } => {
+                    return Err(RuntimeError::Redeclaration(bytecode.local_names[*name].clone()));
+                },
+                Op::EndScope { len } => self.locals().truncate(*len),
+                Op::LoadGlobal(global) => {
+                    let value = match &self.globals[*global] {
+                        Some(global) => global.value.clone(),
+                        None => return Err(RuntimeError::UndefinedVariable(bytecode.global_names[*global].clone())),
+                    };
+                    self.stack.push(value);
+                },
+                Op::DefineGlobal { global, is_const } => {
+                    let value = self.pop();
+                    if self.globals[*global].is_some() {
+                        return Err(RuntimeError::Redeclaration(bytecode.global_names[*global].clone()));
+                    }
+                    self.globals[*global] = Some(Global { value, is_const: *is_const });
+                },
+                Op::StoreGlobal(global) => {
+                    let value = self.pop();
+                    let name = || bytecode.global_names[*global].clone();
+                    match &mut self.globals[*global] {
+                        Some(global) if global.is_const => return Err(RuntimeError::AssignToConst(name())),
+                        Some(global) => global.value = value,
+                        None => return Err(RuntimeError::UndefinedVariable(name())),
+                    }
+                },
+                Op::Add => self.binop(interp::add)?,
+                Op::Sub => self.binop(interp::sub)?,
+                Op::Mul => self.binop(interp::mul)?,
+                Op::Div => self.binop(interp::div)?,
+                Op::Mod => self.binop(interp::rem)?,
+                Op::Pow => self.binop(interp::pow)?,
+                Op::Eq => self.binop(|l, r| Ok(Value::Bool(interp::values_equal(&l, &r))))?,
+                Op::NotEq => self.binop(|l, r| Ok(Value::Bool(!interp::values_equal(&l, &r))))?,
+                Op::Less => self.comparison(|ord| ord == Ordering::Less)?,
+                Op::Greater => self.comparison(|ord| ord == Ordering::Greater)?,
+                Op::LessEq => self.comparison(|ord| ord != Ordering::Greater)?,
+                Op::GreaterEq => self.comparison(|ord| ord != Ordering::Less)?,
+                Op::Neg => {
+                    let value = match self.pop() {
+                        Value::Int(i) => Value::Int(i.checked_neg().ok_or(RuntimeError::IntegerOverflow { op: "-" })?),
+                        Value::Float(f) => Value::Float(-f),
+                        other => {
+                            return Err(RuntimeError::TypeError(format!("can't negate {}", other.ty().with_article())));
+                        },
+                    };
+                    self.stack.push(value);
+                },
+                Op::Not => {
+                    let b = self.pop_bool("operand of !")?;
+                    self.stack.push(Value::Bool(!b));
+                },
+                Op::Jump(target) => self.jump(*target),
+                Op::JumpIfFalse { target, what } => {
+                    if !self.pop_bool(what)? {
+                        self.jump(*target);
+                    }
+                },
+                Op::JumpIfTrue { target, what } => {
+                    if self.pop_bool(what)? {
+                        self.jump(*target);
+                    }
+                },
+                Op::Call { func, argc } => {
+                    let args = self.pop_args(*func, *argc)?;
+                    if self.frames.len() > MAX_CALL_DEPTH {
+                        return Err(RuntimeError::StackOverflow);
+                    }
+                    let locals = self.bind_args(*func, args)?;
+                    self.frames.push(Frame { func: Some(*func), pc: 0, locals });
+                },
+                Op::CallBuiltin { builtin, argc } => {
+                    let args = self.stack.split_off(self.stack.len() - argc);
+                    let ret = builtin.call(args, &self.output)?;
+                    self.stack.push(ret);
+                },
+                Op::CallUndefined { name, span } => {
+                    return Err(RuntimeError::UndefinedFunction { name: name.clone(), span: *span });
+                },
+                Op::TailCall { argc } => {
+                    let func = self.frames.last().unwrap().func.unwrap();
+                    let args = self.pop_args(func, *argc)?;
+                    let locals = self.bind_args(func, args)?;
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.pc = 0;
+                    frame.locals = locals;
+                },
+                Op::Return => {
+                    let ret = self.pop();
+                    let Some(func) = self.frames.last().unwrap().func else {
+                        return Err(RuntimeError::ReturnOutsideFunction);
+                    };
+                    let func = &bytecode.functions[func];
+                    if let Some(ty) = func.return_type {
+                        if ret.ty() != ty {
+                            return Err(RuntimeError::TypeError(format!(
+                                "func {} must return {}, not {}",
+                                func.name,
+                                ty.with_article(),
+                                ret.type_name(),
+                            )));
+                        }
+                    }
+                    self.frames.pop();
+                    self.stack.push(ret);
+                },
+                Op::NotInLoop { keyword } => return Err(RuntimeError::NotInLoop { keyword }),
+                Op::Halt => break,
+            }
+        }
+
+        let mut vars = HashMap::new();
+        for (name, global) in bytecode.global_names.iter().zip(self.globals) {
+            if let Some(global) = global {
+                vars.insert(name.clone(), global.value);
+            }
+        }
+        for local in self.frames.pop().unwrap().locals {
+            vars.insert(bytecode.local_names[local.name].clone(), local.value);
+        }
+        Ok(vars)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("bytecode popped an empty stack")
+    }
+
+    /// Pop a value that has to be a bool. `what` describes it for the error message.
+    fn pop_bool(&mut self, what: &str) -> Result<bool, RuntimeError> {
+        match self.pop() {
+            Value::Bool(b) => Ok(b),
+            other => Err(RuntimeError::TypeError(format!("{what} must be a bool, not {}", other.type_name()))),
+        }
+    }
+
+    fn binop(&mut self, op: fn(Value, Value) -> Result<Value, RuntimeError>) -> Result<(), RuntimeError> {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        self.stack.push(op(lhs, rhs)?);
+        Ok(())
+    }
+
+    /// Compare the top two values, pushing false if either is NaN
+    fn comparison(&mut self, test: fn(Ordering) -> bool) -> Result<(), RuntimeError> {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        let ord = interp::compare(lhs, rhs)?;
+        self.stack.push(Value::Bool(ord.is_some_and(test)));
+        Ok(())
+    }
+
+    fn locals(&mut self) -> &mut Vec<Local> {
+        &mut self.frames.last_mut().unwrap().locals
+    }
+
+    fn jump(&mut self, target: usize) {
+        self.frames.last_mut().unwrap().pc = target;
+    }
+
+    /// Pop the `argc` args of a call of `functions[func]`, checking there are the right number
+    fn pop_args(&mut self, func: usize, argc: usize) -> Result<Vec<Value>, RuntimeError> {
+        let args = self.stack.split_off(self.stack.len() - argc);
+        let func = &self.bytecode.functions[func];
+        if func.arg_names.len() != argc {
+            return Err(RuntimeError::ArityMismatch {
+                func: func.name.clone(),
+                expected: func.arg_names.len(),
+                got: argc,
+            });
+        }
+        Ok(args)
+    }
+
+    /// Type check `args`, and make them the first locals of a new frame for `functions[func]`
+    fn bind_args(&self, func: usize, args: Vec<Value>) -> Result<Vec<Local>, RuntimeError> {
+        let func = &self.bytecode.functions[func];
+        let mut locals = Vec::with_capacity(args.len());
+        for (i, (ty, value)) in func.arg_types.iter().zip(args).enumerate() {
+            if let Some(ty) = ty {
+                if value.ty() != *ty {
+                    return Err(RuntimeError::TypeError(format!(
+                        "arg {} of func {} must be {}, not {}",
+                        self.bytecode.local_names[func.arg_names[i]],
+                        func.name,
+                        ty.with_article(),
+                        value.type_name(),
+                    )));
+                }
+            }
+            locals.push(Local { value, name: func.arg_names[i], is_const: false });
+        }
+        Ok(locals)
+    }
+}
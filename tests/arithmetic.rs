@@ -0,0 +1,139 @@
+use foolang::ast::ParseError;
+use foolang::interp::{RuntimeError, Value};
+use foolang::lex::LexErrorKind;
+use foolang::{run_source, Error};
+
+mod common;
+use common::{eval, eval_err};
+
+#[test]
+fn multiplication_binds_tighter_than_addition() {
+    assert_eq!(eval("2 + 3 * 4"), Value::Int(14));
+    assert_eq!(eval("1 + 2 * 3"), Value::Int(7));
+    assert_eq!(eval("10 - 2 - 3"), Value::Int(5));
+    assert_eq!(eval("24 / 4 / 2"), Value::Int(3));
+    assert_eq!(eval("1 + 6 / 2 - 4 * 2"), Value::Int(-4));
+}
+
+#[test]
+fn parentheses_group() {
+    assert_eq!(eval("(1 + 2) * 3"), Value::Int(9));
+    assert_eq!(eval("10 - (2 - 3)"), Value::Int(11));
+    assert_eq!(eval("((((7))))"), Value::Int(7));
+}
+
+#[test]
+fn negation() {
+    assert_eq!(eval("-5 + 2"), Value::Int(-3));
+    assert_eq!(eval("--5"), Value::Int(5));
+    assert_eq!(eval("-(2 * 3)"), Value::Int(-6));
+    // `**` binds tighter than negation
+    assert_eq!(eval("-2 ** 2"), Value::Int(-4));
+    assert_eq!(eval("(-2) ** 2"), Value::Int(4));
+    assert_eq!(eval("-2 ** 2 * 3"), Value::Int(-12));
+    assert_eq!(eval("-(-2) ** 3"), Value::Int(8));
+    assert_eq!(eval("2.0 ** -2 ** 2"), Value::Float(1.0 / 16.0));
+    assert_eq!(eval("-5 + 10"), Value::Int(5));
+    assert_eq!(eval("-1.5"), Value::Float(-1.5));
+}
+
+#[test]
+fn ints_are_64_bit() {
+    assert_eq!(eval("3000000000"), Value::Int(3_000_000_000));
+    assert_eq!(eval("3000000000").to_string(), "3000000000");
+    assert_eq!(eval("9223372036854775807 - 1"), Value::Int(i64::MAX - 1));
+}
+
+#[test]
+fn int_literals_out_of_range() {
+    // the smallest int is only a literal when it's negated
+    assert_eq!(eval("-9223372036854775808"), Value::Int(i64::MIN));
+    assert!(matches!(
+        run_source("begin { var v = 999999999999999999999; }"),
+        Err(Error::Parse(ParseError::IntegerOutOfRange("999999999999999999999", _))),
+    ));
+}
+
+#[test]
+fn floats() {
+    assert_eq!(eval("1.5 + 2.5 == 4.0"), Value::Bool(true));
+    assert_eq!(eval("1 + 2.0 == 3.0"), Value::Bool(true));
+    assert_eq!(eval("1 + 2.0"), Value::Float(3.0));
+    assert_eq!(eval("7 / 2.0"), Value::Float(3.5));
+    assert_eq!(eval("0.1 * 3"), Value::Float(0.1 * 3.0));
+}
+
+#[test]
+fn modulo() {
+    assert_eq!(eval("7 % 3 == 1"), Value::Bool(true));
+    assert_eq!(eval("10 % 2 == 0"), Value::Bool(true));
+    assert_eq!(eval("1 + 7 % 3 * 2"), Value::Int(3));
+    assert!(matches!(eval_err("1 % 0"), RuntimeError::DivisionByZero));
+}
+
+#[test]
+fn int_division_truncates_and_checks_for_zero() {
+    assert_eq!(eval("7 / 2"), Value::Int(3));
+    assert_eq!(eval("-7 / 2"), Value::Int(-3));
+    assert!(matches!(eval_err("1 / 0"), RuntimeError::DivisionByZero));
+    assert!(matches!(eval_err("1 / (2 - 2)"), RuntimeError::DivisionByZero));
+}
+
+#[test]
+fn power() {
+    assert_eq!(eval("2 ** 10 == 1024"), Value::Bool(true));
+    assert_eq!(eval("2 ** 3 ** 2"), Value::Int(512));
+    assert_eq!(eval("2.0 ** -1"), Value::Float(0.5));
+    assert!(matches!(eval_err("2 ** -1"), RuntimeError::NegativeExponent));
+    assert!(matches!(eval_err("2 ** 64"), RuntimeError::IntegerOverflow { .. }));
+}
+
+#[test]
+fn int_overflow_is_an_error() {
+    assert!(matches!(eval_err("9223372036854775807 * 2"), RuntimeError::IntegerOverflow { op: "*" }));
+    assert!(matches!(eval_err("9223372036854775807 + 1"), RuntimeError::IntegerOverflow { op: "+" }));
+    assert!(matches!(eval_err("-9223372036854775807 - 2"), RuntimeError::IntegerOverflow { op: "-" }));
+    assert!(matches!(eval_err("-(-9223372036854775807 - 1)"), RuntimeError::IntegerOverflow { .. }));
+    assert!(matches!(eval_err("(-9223372036854775807 - 1) / -1"), RuntimeError::IntegerOverflow { .. }));
+    assert_eq!(eval("4611686018427387903 * 2"), Value::Int(i64::MAX - 1));
+}
+
+#[test]
+fn radix_literals() {
+    assert_eq!(eval("0xFF == 255"), Value::Bool(true));
+    assert_eq!(eval("0b101 == 5"), Value::Bool(true));
+    assert_eq!(eval("0o17 == 15"), Value::Bool(true));
+    assert_eq!(eval("-0x8000000000000000"), Value::Int(i64::MIN));
+    assert!(matches!(run_source("begin { var v = 0b102; }"), Err(Error::Lex(e)) if matches!(e.kind, LexErrorKind::InvalidDigit { digit: '2', radix: 2 })));
+}
+
+#[test]
+fn digit_separators() {
+    assert_eq!(eval("1_000 == 1000"), Value::Bool(true));
+    assert_eq!(eval("0xFF_FF"), Value::Int(0xFFFF));
+    assert_eq!(eval("1_000.000_5"), Value::Float(1000.0005));
+    assert!(matches!(run_source("begin { var v = 1__0; }"), Err(Error::Lex(e)) if matches!(e.kind, LexErrorKind::MisplacedSeparator)));
+}
+
+#[test]
+fn math_builtins() {
+    assert_eq!(eval("abs(-3) == 3"), Value::Bool(true));
+    assert_eq!(eval("abs(-2.5)"), Value::Float(2.5));
+    assert_eq!(eval("max(2, 5) == 5"), Value::Bool(true));
+    assert_eq!(eval("min(2, 5)"), Value::Int(2));
+    assert_eq!(eval("max(2.5, 5.5)"), Value::Float(5.5));
+    assert!(matches!(eval_err("max(2, 5.5)"), RuntimeError::TypeError(_)));
+    assert!(matches!(eval_err("abs(-9223372036854775807 - 1)"), RuntimeError::IntegerOverflow { .. }));
+    assert!(matches!(eval_err("abs(\"x\")"), RuntimeError::TypeError(_)));
+}
+
+#[test]
+fn converting_between_ints_and_floats() {
+    assert_eq!(eval("to_int(3.9) == 3"), Value::Bool(true));
+    assert_eq!(eval("to_int(-3.9)"), Value::Int(-3));
+    assert_eq!(eval("to_float(2) == 2.0"), Value::Bool(true));
+    assert_eq!(eval("to_float(2)"), Value::Float(2.0));
+    assert_eq!(eval("to_int(\"42\")"), Value::Int(42));
+    assert_eq!(eval("to_float(\"1.5\")"), Value::Float(1.5));
+    assert!(matches!(eval_err("to_int(\"4x\")"), RuntimeError::ParseValueError { .. }));
+}
@@ -0,0 +1,94 @@
+use foolang::ast::ParseError;
+use foolang::interp::{RuntimeError, Value};
+use foolang::{run_source, Error};
+
+mod common;
+use common::{run, run_begin, run_printing};
+
+#[test]
+fn chained_assignment_assigns_every_variable() {
+    let vars = run("begin { var a = 0; var b = 0; a = b = 3; }");
+    assert_eq!(vars["a"], Value::Int(3));
+    assert_eq!(vars["b"], Value::Int(3));
+}
+
+#[test]
+fn assignment_gives_the_assigned_value() {
+    let vars = run("begin { var b = 0; var a = (b = 5) + 1; }");
+    assert_eq!(vars["a"], Value::Int(6));
+    assert_eq!(vars["b"], Value::Int(5));
+}
+
+#[test]
+fn element_assignment_is_an_expression() {
+    let vars = run_begin("var arr = [1, 2, 3]; var x = arr[1] = 9; var y = [arr[0] = 7];");
+    assert_eq!(vars["arr"].to_string(), "[7, 9, 3]");
+    assert_eq!(vars["x"].to_string(), "9");
+    assert_eq!(vars["y"].to_string(), "[7]");
+}
+
+#[test]
+fn assigning_to_a_nested_element() {
+    let vars = run_begin("var grid = [[1, 2], [3, 4]]; grid[0][1] = 7; var m = #{\"a\": [1]}; m[\"a\"][0] = 4;");
+    assert_eq!(vars["grid"].to_string(), "[[1, 7], [3, 4]]");
+    assert_eq!(vars["m"].to_string(), "#{\"a\": [4]}");
+}
+
+#[test]
+fn compound_assignment_to_an_element() {
+    let vars = run_begin("var a = [1, 2, 3]; var i = 1; a[i] += 10; a[2] *= a[i];");
+    assert_eq!(vars["a"].to_string(), "[1, 12, 36]");
+}
+
+#[test]
+fn assigning_to_an_element_copies_shared_elements() {
+    let vars = run_begin("var a = [1, 2]; var b = a; b[0] = 100;");
+    assert_eq!(vars["a"].to_string(), "[1, 2]");
+    assert_eq!(vars["b"].to_string(), "[100, 2]");
+}
+
+#[test]
+fn assigning_out_of_bounds_doesnt_grow_the_array() {
+    let result = run_source("begin { var a = [1, 2, 3]; a[3] = 4; }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::IndexOutOfBounds { index: 3, len: 3 }))));
+}
+
+#[test]
+fn only_variables_and_elements_can_be_assigned() {
+    let result = run_source("begin { var a = 1; a + 1 = 2; }");
+    assert!(matches!(result, Err(Error::Parse(ParseError::UnexpectedToken(..)))));
+}
+
+#[test]
+fn long_assignment_chain_is_too_deep_rather_than_overflowing() {
+    let src = format!("begin {{ var a = 0; {}1; }}", "a = ".repeat(100_000));
+    assert!(matches!(run_source(&src), Err(Error::Parse(ParseError::NestingTooDeep(_)))));
+}
+
+#[test]
+fn assigning_to_a_captured_variable_is_an_error() {
+    let result = run_source("begin { var c = 1; var f = func() { c = 2; }; f(); }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::AssignToCaptured(name))) if name == "c"));
+
+    // a block function only has copies once it's used as a value
+    let result = run_source("begin { var c = 1; func bump() { c += 1; } bump(); var f = bump; f(); }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::AssignToCaptured(name))) if name == "c"));
+}
+
+#[test]
+fn closures_capture_by_value() {
+    let vars = run_begin("var c = 1; var f = func() { return c; }; c = 2; var got = f();");
+    assert_eq!(vars["got"].to_string(), "1");
+    assert_eq!(vars["c"].to_string(), "2");
+}
+
+#[test]
+fn compound_assignment_evaluates_the_index_once() {
+    let src = "var n = 0; func next() { n += 1; print(n); return 0; } begin { var a = [10, 20]; a[next()] += 1; }";
+    assert_eq!(run_printing(src), "1\n");
+
+    // reading and writing the element use the same index
+    let vars = run("var n = -1; func next() { n += 1; return n; } begin { var a = [10, 20]; a[next()] += 5; var last = n; }");
+    assert_eq!(vars["a"].to_string(), "[15, 20]");
+    assert_eq!(vars["last"], Value::Int(0));
+}
@@ -0,0 +1,91 @@
+use foolang::check::SemanticError;
+use foolang::interp::{RuntimeError, Value};
+use foolang::{run_source, Error};
+
+mod common;
+use common::run;
+
+#[test]
+fn function_defined_in_a_block_is_only_visible_there() {
+    let src = "func outer() { func helper() { return 1; } return helper(); } begin { var a = outer(); }";
+    assert_eq!(run(src)["a"], Value::Int(1));
+
+    let src = "func outer() { func helper() { return 1; } return helper(); } begin { var a = helper(); }";
+    let Err(Error::Semantic(errors)) = run_source(src) else {
+        panic!("calling helper outside of outer should fail the check");
+    };
+    assert!(matches!(&errors[..], [SemanticError::UndefinedFunction { name, .. }] if name == "helper"));
+}
+
+#[test]
+fn functions_can_be_called_before_their_definitions() {
+    let vars = run("begin { var a = later(); func later() { return 2; } }");
+    assert_eq!(vars["a"], Value::Int(2));
+}
+
+#[test]
+fn functions_in_the_same_block_can_call_each_other() {
+    let src = "
+        func parity(n) {
+            func is_even(n) { if n == 0 { return true; } return is_odd(n - 1); }
+            func is_odd(n) { if n == 0 { return false; } return is_even(n - 1); }
+            return [is_even(n), is_odd(n)];
+        }
+        begin { var a = parity(7); }
+    ";
+    assert_eq!(run(src)["a"].to_string(), "[false, true]");
+}
+
+#[test]
+fn functions_see_locals_as_they_are_when_called() {
+    let src = "
+        func outer() {
+            var k = 2;
+            func helper() { return k; }
+            k = 5;
+            return helper();
+        }
+        begin { var a = outer(); }
+    ";
+    assert_eq!(run(src)["a"], Value::Int(5));
+}
+
+#[test]
+fn functions_can_assign_to_locals_they_see() {
+    let vars = run("begin { var count = 0; func bump() { count += 1; } bump(); bump(); }");
+    assert_eq!(vars["count"], Value::Int(2));
+}
+
+#[test]
+fn calling_a_function_before_a_local_it_uses_is_declared_is_an_error() {
+    let result = run_source("begin { var a = helper(); var k = 1; func helper() { return k; } }");
+    assert!(matches!(
+        result,
+        Err(Error::Runtime(RuntimeError::UndefinedFunction { name, .. })) if name == "helper",
+    ));
+}
+
+#[test]
+fn function_used_as_a_value_keeps_copies_of_the_locals() {
+    let src = "
+        func make() {
+            var k = 1;
+            func get() { return k; }
+            var copy = get;
+            k = 2;
+            return [copy, get];
+        }
+        begin { var fs = make(); var a = fs[0](); var b = fs[1](); }
+    ";
+    let vars = run(src);
+    assert_eq!(vars["a"], Value::Int(1));
+    assert_eq!(vars["b"], Value::Int(2));
+}
+
+#[test]
+fn defining_a_function_twice_in_a_block_is_an_error() {
+    let Err(Error::Semantic(errors)) = run_source("begin { func f() { return 1; } func f() { return 2; } }") else {
+        panic!("defining f twice should fail the check");
+    };
+    assert!(matches!(&errors[..], [SemanticError::Redeclaration { name, .. }] if name == "f"));
+}
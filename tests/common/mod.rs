@@ -0,0 +1,54 @@
+// each test crate only uses some of these
+#![allow(dead_code)]
+
+use foolang::ast::{self, Item};
+use foolang::interp::{Program, RuntimeError, Value};
+use foolang::{check, lex, run_source, Error};
+use std::collections::HashMap;
+
+/// Lex and parse `src`, which has to be valid
+pub fn parse(src: &str) -> Vec<Item<'_>> {
+    let tokens = lex::lex_tokens(src).map(Result::unwrap);
+    ast::parse_items(tokens).map(Result::unwrap).collect()
+}
+
+/// Run `src`, giving its begin block's variables
+pub fn run(src: &str) -> HashMap<String, Value> {
+    run_source(src).unwrap_or_else(|e| panic!("{src} failed: {e}"))
+}
+
+/// Run `body` as a begin block, giving its variables
+pub fn run_begin(body: &str) -> HashMap<String, Value> {
+    run(&format!("begin {{ {body} }}"))
+}
+
+/// The value of `expr`, evaluated in a begin block
+pub fn eval(expr: &str) -> Value {
+    run_begin(&format!("var v = {expr};")).remove("v").unwrap()
+}
+
+/// The runtime error evaluating `expr` in a begin block gives
+pub fn eval_err(expr: &str) -> RuntimeError {
+    match run_source(&format!("begin {{ var v = {expr}; }}")) {
+        Err(Error::Runtime(e)) => e,
+        other => panic!("{expr} should have failed at runtime, not given {other:?}"),
+    }
+}
+
+/// Run `src` without checking it first, so mistakes the checker would find
+/// are left for the interpreter to find
+pub fn run_unchecked(src: &str) -> Result<(), RuntimeError> {
+    let program = Program::from_items(parse(src).into_iter()).unwrap();
+    program.execute().map(drop)
+}
+
+/// Run `src`, giving what it prints
+pub fn run_printing(src: &str) -> String {
+    let mut out = Vec::new();
+    {
+        let program = Program::from_items(parse(src).into_iter()).unwrap().with_output(&mut out);
+        check::check_program(&program).unwrap();
+        program.execute().unwrap();
+    }
+    String::from_utf8(out).unwrap()
+}
@@ -0,0 +1,139 @@
+use foolang::interp::{RuntimeError, Value};
+use foolang::{run_source, Error};
+
+mod common;
+use common::run;
+
+#[test]
+fn if_runs_the_branch_the_condition_picks() {
+    let classify = |n: i64| {
+        let src = format!("begin {{ var n = {n}; var kind = 0; if n < 0 {{ kind = -1; }} else if n == 0 {{ kind = 0; }} else {{ kind = 1; }} }}");
+        run(&src)["kind"].clone()
+    };
+    assert_eq!(classify(-5), Value::Int(-1));
+    assert_eq!(classify(0), Value::Int(0));
+    assert_eq!(classify(5), Value::Int(1));
+
+    let vars = run("begin { var x = 1; if false { x = 2; } }");
+    assert_eq!(vars["x"], Value::Int(1));
+}
+
+#[test]
+fn conditions_have_to_be_bools() {
+    for src in [
+        "begin { if 5 { } }",
+        "begin { while 1 { } }",
+        "begin { var x = if 0 then 1 else 2; }",
+        "begin { var x = !1; }",
+        "begin { var x = 1 && true; }",
+    ] {
+        assert!(matches!(run_source(src), Err(Error::Runtime(RuntimeError::TypeError(_)))), "{src}");
+    }
+}
+
+#[test]
+fn while_loop_sums() {
+    let vars = run("begin { var i = 1; var sum = 0; while i <= 5 { sum = sum + i; i = i + 1; } }");
+    assert_eq!(vars["sum"], Value::Int(15));
+    assert_eq!(vars["i"], Value::Int(6));
+}
+
+#[test]
+fn compound_assignment() {
+    let vars = run("begin { var sum = 0; var i = 0; while i < 5 { i += 1; sum += i; } var p = 3; p *= 4; p -= 2; p /= 5; }");
+    assert_eq!(vars["sum"], Value::Int(15));
+    assert_eq!(vars["p"], Value::Int(2));
+}
+
+#[test]
+fn for_loop() {
+    let vars = run("begin { var sum = 0; for (var i = 0; i < 10; i += 1) { sum += i; } }");
+    assert_eq!(vars["sum"], Value::Int(45));
+    // the loop variable is scoped to the loop
+    assert!(!vars.contains_key("i"));
+}
+
+#[test]
+fn for_each_over_ranges_and_arrays() {
+    assert_eq!(run("begin { var sum = 0; for i in 0..5 { sum += i; } }")["sum"], Value::Int(10));
+    assert_eq!(run("begin { var sum = 0; for i in 0..=5 { sum += i; } }")["sum"], Value::Int(15));
+    assert_eq!(run("begin { var sum = 0; for i in 5..0 { sum += i; } }")["sum"], Value::Int(0));
+    assert_eq!(run("begin { var s = \"\"; for x in [\"a\", \"b\"] { s += x; } }")["s"], Value::String("ab".into()));
+    assert_eq!(run("begin { var r = 1..4; }")["r"].to_string(), "[1, 2, 3]");
+    assert!(matches!(run_source("begin { for x in 5 { } }"), Err(Error::Runtime(RuntimeError::TypeError(_)))));
+}
+
+#[test]
+fn break_and_continue() {
+    let vars = run("begin { var last = 0; for i in 0..100 { if i == 3 { break; } last = i; } }");
+    assert_eq!(vars["last"], Value::Int(2));
+    let vars = run("begin { var sum = 0; for i in 0..5 { if i % 2 == 1 { continue; } sum += i; } }");
+    assert_eq!(vars["sum"], Value::Int(6));
+    // continue in a for loop still runs the step
+    let vars = run("begin { var n = 0; for (var i = 0; i < 5; i += 1) { if i < 4 { continue; } n = i; } }");
+    assert_eq!(vars["n"], Value::Int(4));
+    // break only leaves the innermost loop
+    let vars = run("begin { var n = 0; var i = 0; while i < 3 { i += 1; while true { break; } n += 1; } }");
+    assert_eq!(vars["n"], Value::Int(3));
+}
+
+#[test]
+fn and_and_or_short_circuit() {
+    let src = "func boom() { return 1 / 0 == 0; } begin { var a = false && boom(); var b = true || boom(); }";
+    let vars = run(src);
+    assert_eq!(vars["a"], Value::Bool(false));
+    assert_eq!(vars["b"], Value::Bool(true));
+    let src = "func boom() { return 1 / 0 == 0; } begin { var a = true && boom(); }";
+    assert!(matches!(run_source(src), Err(Error::Runtime(RuntimeError::DivisionByZero))));
+}
+
+#[test]
+fn not() {
+    let vars = run("begin { var a = !true; var b = !(1 < 2); var c = !!true; }");
+    assert_eq!(vars["a"], Value::Bool(false));
+    assert_eq!(vars["b"], Value::Bool(false));
+    assert_eq!(vars["c"], Value::Bool(true));
+}
+
+#[test]
+fn early_return_skips_the_rest() {
+    let src = "
+        var ran = false;
+        func f(x) {
+            if x > 0 {
+                return 1;
+            }
+            ran = true;
+            return 2;
+        }
+        begin { var a = f(1); var skipped = ran; var b = f(-1); var done = ran; }
+    ";
+    let vars = run(src);
+    assert_eq!(vars["a"], Value::Int(1));
+    assert_eq!(vars["skipped"], Value::Bool(false));
+    assert_eq!(vars["b"], Value::Int(2));
+    assert_eq!(vars["done"], Value::Bool(true));
+}
+
+#[test]
+fn returning_out_of_loops() {
+    let src = "func find(arr, x) { for i in 0..len(arr) { if arr[i] == x { return i; } } return -1; }
+        begin { var a = find([5, 6, 7], 6); var b = find([5], 9); }";
+    let vars = run(src);
+    assert_eq!(vars["a"], Value::Int(1));
+    assert_eq!(vars["b"], Value::Int(-1));
+}
+
+#[test]
+fn return_in_begin_is_an_error() {
+    assert!(matches!(run_source("begin { return 1; }"), Err(Error::Runtime(RuntimeError::ReturnOutsideFunction))));
+}
+
+#[test]
+fn ranges_are_exclusive_unless_inclusive() {
+    let vars = run("begin { var a = 0..3; var b = 0..=3; var c = 3..=3; var d = 3..3; }");
+    assert_eq!(vars["a"].to_string(), "[0, 1, 2]");
+    assert_eq!(vars["b"].to_string(), "[0, 1, 2, 3]");
+    assert_eq!(vars["c"].to_string(), "[3]");
+    assert_eq!(vars["d"].to_string(), "[]");
+}
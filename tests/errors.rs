@@ -0,0 +1,68 @@
+use foolang::interp::{ProgramError, RuntimeError};
+use foolang::{render_diagnostic, run_source, Error};
+
+mod common;
+use common::run_unchecked;
+
+#[test]
+fn undefined_variables_are_a_runtime_error_too() {
+    assert!(matches!(run_unchecked("begin { var y = x; }"), Err(RuntimeError::UndefinedVariable(name)) if name == "x"));
+    assert!(matches!(run_unchecked("begin { nope(); }"), Err(RuntimeError::UndefinedFunction { name, .. }) if name == "nope"));
+    assert!(matches!(run_unchecked("func f(a) { } begin { f(); }"), Err(RuntimeError::ArityMismatch { expected: 1, got: 0, .. })));
+    assert!(matches!(run_unchecked("begin { break; }"), Err(RuntimeError::NotInLoop { keyword: "break" })));
+}
+
+#[test]
+fn begin_blocks() {
+    assert!(run_source("begin { }").unwrap().is_empty());
+    assert!(run_source("func f() { } begin { }").unwrap().is_empty());
+    assert!(matches!(run_source("func f() { }"), Err(Error::Program(ProgramError::MissingBegin))));
+    assert!(matches!(run_source(""), Err(Error::Program(ProgramError::MissingBegin))));
+}
+
+#[test]
+fn multiple_begin_blocks_are_an_error() {
+    let result = run_source("begin { }\nbegin { }");
+    let Err(Error::Program(ProgramError::MultipleBegin { first_span, second_span })) = result else {
+        panic!("two begin blocks should be an error");
+    };
+    assert_eq!((first_span.start, second_span.start), (0, 10));
+}
+
+#[test]
+fn errors_are_values_rather_than_panics() {
+    assert!(matches!(run_source("begin { var x = 1 / 0; }"), Err(Error::Runtime(RuntimeError::DivisionByZero))));
+    assert!(matches!(run_source("begin { var x = 1 +; }"), Err(Error::Parse(_))));
+    assert!(matches!(run_source("begin { var x = 1 @ 2; }"), Err(Error::Lex(_))));
+    assert!(matches!(run_source("begin { var x = 1; var x = 2; }"), Err(Error::Semantic(_))));
+    assert!(matches!(run_source("begin { var x = [1][5]; }"), Err(Error::Runtime(RuntimeError::IndexOutOfBounds { .. }))));
+}
+
+#[test]
+fn error_messages() {
+    let message = |src: &str| run_source(src).unwrap_err().to_string();
+    assert_eq!(message("begin {\n\n  var y @ 2;\n}"), "Lex error: unexpected character '@' at line 3, column 9");
+    assert_eq!(message("begin { var x = 1); }"), "Parse error: expected `;`, found `)` at byte 17");
+    assert_eq!(message("begin { }\nbegin { }"), "Error: program has more than one begin block, at byte 0 and at byte 10");
+    assert_eq!(message("begin { print(x); y(); }"), "Error: variable x is not defined (used at byte 14)\nError: no definition for func y (called at byte 18)");
+    assert_eq!(message("begin { var x = 1 / 0; }"), "Runtime error: division by zero");
+}
+
+#[test]
+fn rendered_error_points_at_the_source() {
+    let src = "begin {\n    var x = 1;\n    x = y;\n}";
+    let err = run_source(src).unwrap_err();
+    let Error::Semantic(errors) = &err else {
+        panic!("reading y should fail the check");
+    };
+    let rendered = render_diagnostic(src, err.span().unwrap(), &format!("Error: {}", errors[0]));
+    assert_eq!(
+        rendered,
+        "Error: variable y is not defined (used at byte 31)\n  --> line 3, column 9\n  |\n3 |     x = y;\n  |         ^",
+    );
+
+    let src = "func f() { return g(); }\nbegin { f(); }";
+    let err = run_source(src).unwrap_err();
+    let rendered = render_diagnostic(src, err.span().unwrap(), &err.to_string());
+    assert!(rendered.ends_with("1 | func f() { return g(); }\n  |                   ^"), "{rendered}");
+}
@@ -0,0 +1,169 @@
+use foolang::check::SemanticError;
+use foolang::interp::{ProgramError, RuntimeError, Value};
+use foolang::{run_source, Error};
+
+mod common;
+use common::{run, run_printing};
+
+#[test]
+fn print_writes_a_line_to_the_output() {
+    assert_eq!(run_printing("begin { print(42); }"), "42\n");
+    assert_eq!(run_printing("begin { print(\"a\"); print([1, \"b\"]); print(1.0); }"), "a\n[1, \"b\"]\n1.0\n");
+}
+
+#[test]
+fn calls_as_statements_discard_their_result() {
+    assert_eq!(run_printing("func f(x) { return x * 2; } begin { f(1); print(1 + 2); }"), "3\n");
+    assert_eq!(run("func f(x) { return x * 2; } begin { f(1); }").len(), 0);
+}
+
+#[test]
+fn functions_without_a_return_give_unit() {
+    let vars = run("func greet(name) { print(\"hi ${name}\"); } func early() { return; } begin { greet(\"x\"); var a = greet(\"y\"); var b = early(); }");
+    assert_eq!(vars["a"], Value::Unit);
+    assert_eq!(vars["b"], Value::Unit);
+}
+
+#[test]
+fn unit_return_type() {
+    let vars = run("func f() -> unit { return; } func g() -> unit { } begin { var a = f(); var b = g(); }");
+    assert_eq!(vars["a"], Value::Unit);
+    assert_eq!(vars["b"], Value::Unit);
+    let result = run_source("func f() -> unit { return 1; } begin { f(); }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::TypeError(_)))));
+    let result = run_source("func f() -> int { } begin { f(); }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::TypeError(_)))));
+}
+
+#[test]
+fn single_returns_work() {
+    let vars = run("func f(x) { if x { return 1; } else { return 2; } } begin { var a = f(true); var b = f(false); }");
+    assert_eq!(vars["a"], Value::Int(1));
+    assert_eq!(vars["b"], Value::Int(2));
+}
+
+#[test]
+fn recursion() {
+    let src = "func factorial(n) { if n <= 1 { return 1; } return n * factorial(n - 1); } begin { var f = factorial(5); }";
+    assert_eq!(run(src)["f"], Value::Int(120));
+
+    let result = run_source("func forever(n) { return 1 + forever(n + 1); } begin { var x = forever(0); }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::StackOverflow))));
+}
+
+#[test]
+fn tail_recursion_doesnt_nest() {
+    let src = "func count(n, acc) { if n == 0 { return acc; } return count(n - 1, acc + 1); } begin { var x = count(1000000, 0); }";
+    assert_eq!(run(src)["x"], Value::Int(1_000_000));
+}
+
+#[test]
+fn functions_can_call_ones_defined_later() {
+    let is_even = "func is_even(n) { if n == 0 { return true; } return is_odd(n - 1); }";
+    let is_odd = "func is_odd(n) { if n == 0 { return false; } return is_even(n - 1); }";
+    let begin = "begin { var a = is_even(10); var b = is_odd(7); }";
+    for src in [
+        format!("{is_even} {is_odd} {begin}"),
+        format!("{is_odd} {is_even} {begin}"),
+        format!("{begin} {is_even} {is_odd}"),
+        format!("{is_odd} {begin} {is_even}"),
+    ] {
+        let vars = run(&src);
+        assert_eq!(vars["a"], Value::Bool(true), "{src}");
+        assert_eq!(vars["b"], Value::Bool(true), "{src}");
+    }
+}
+
+#[test]
+fn defining_a_function_twice_is_an_error() {
+    let src = "func foo() { return 1; }\nfunc foo() { return 2; }\nbegin { }";
+    let Err(Error::Program(ProgramError::DuplicateFunction { name, first_span, second_span })) = run_source(src) else {
+        panic!("defining foo twice should fail");
+    };
+    assert_eq!(name, "foo");
+    assert_eq!(first_span.start, 5);
+    assert_eq!(second_span.start, 30);
+
+    let result = run_source("func len(x) { return 0; } begin { }");
+    assert!(matches!(result, Err(Error::Program(ProgramError::RedefinedBuiltin { name, .. })) if name == "len"));
+}
+
+#[test]
+fn trailing_commas() {
+    let vars = run("func add(a, b,) { return a + b; } begin { var x = add(1, 2,); }");
+    assert_eq!(vars["x"], Value::Int(3));
+}
+
+#[test]
+fn nested_calls_evaluate_args_left_to_right() {
+    let src = "func show(x) { print(x); return x; } func add(a, b) { return a + b; }
+        begin { print(add(show(1), add(show(2), show(3)))); }";
+    assert_eq!(run_printing(src), "1\n2\n3\n6\n");
+}
+
+#[test]
+fn globals() {
+    let vars = run("var total = 10; const step = 2; func bump() { total += step; return total; } begin { bump(); var x = bump(); }");
+    assert_eq!(vars["x"], Value::Int(14));
+    // globals are initialized in order, and can use functions
+    let vars = run("func two() { return 2; } var a = two(); var b = a * 3; begin { var x = b; }");
+    assert_eq!(vars["x"], Value::Int(6));
+}
+
+#[test]
+fn type_annotations_are_checked_when_called() {
+    let src = "func half(x: int) -> float { return x / 2.0; } begin { var h = half(3); }";
+    assert_eq!(run(src)["h"], Value::Float(1.5));
+    let result = run_source("func half(x: int) -> float { return x / 2.0; } begin { var h = half(3.0); }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::TypeError(_)))));
+    let result = run_source("func f(s: string) -> int { return s; } begin { var x = f(\"a\"); }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::TypeError(_)))));
+}
+
+#[test]
+fn arity_is_checked() {
+    let result = run_source("func add(a, b) { return a + b; } begin { var x = add(1); }");
+    let Err(Error::Semantic(errors)) = result else {
+        panic!("calling add with 1 arg should fail the check");
+    };
+    assert!(matches!(&errors[..], [SemanticError::ArityMismatch { func, expected: 2, got: 1, .. }] if func == "add"));
+
+    // only a call through a value can't be checked before running
+    let result = run_source("func add(a, b) { return a + b; } begin { var f = add; var x = f(1); }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::ArityMismatch { expected: 2, got: 1, .. }))));
+    let result = run_source("begin { var f = func(a) { return a; }; var x = f(1, 2); }");
+    assert!(matches!(result, Err(Error::Runtime(RuntimeError::ArityMismatch { expected: 1, got: 2, .. }))));
+}
+
+#[test]
+fn calling_an_undefined_function_fails_before_running() {
+    let result = run_source("begin { print(1); nope(); }");
+    let Err(Error::Semantic(errors)) = result else {
+        panic!("calling nope should fail the check");
+    };
+    assert!(matches!(&errors[..], [SemanticError::UndefinedFunction { name, span }] if name == "nope" && span.start == 18));
+}
+
+#[test]
+fn functions_are_values() {
+    let src = "func twice(f, x) { return f(f(x)); } func inc(x) { return x + 1; }
+        begin { var a = twice(inc, 5); var g = inc; var b = g(1); var c = [len][0](\"abc\"); }";
+    let vars = run(src);
+    assert_eq!(vars["a"], Value::Int(7));
+    assert_eq!(vars["b"], Value::Int(2));
+    assert_eq!(vars["c"], Value::Int(3));
+    assert!(matches!(run_source("begin { var x = 5; x(); }"), Err(Error::Runtime(RuntimeError::NotCallable { .. }))));
+}
+
+#[test]
+fn lambdas_capture_locals() {
+    let src = "func adder(n) { return func(x) { return x + n; }; }
+        begin { var add2 = adder(2); var add5 = adder(5); var a = add2(1); var b = add5(1); var c = func(x) { return x * 3; }(4); }";
+    let vars = run(src);
+    assert_eq!(vars["a"], Value::Int(3));
+    assert_eq!(vars["b"], Value::Int(6));
+    assert_eq!(vars["c"], Value::Int(12));
+
+    let vars = run("begin { var k = 10; var f = func() { return k; }; k = 20; var x = f(); }");
+    assert_eq!(vars["x"], Value::Int(10));
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "serde")]
+
+use foolang::ast;
+use foolang::json;
+
+mod common;
+use common::parse;
+
+const PROGRAM: &str = r#"
+func fib(n: int) -> int {
+    if n < 2 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+
+const greeting = "hi";
+
+begin {
+    var xs = [fib(10), 2.5];
+    var counts = #{ "a": 1, "b": 2 };
+    var total = 0;
+    for x in 1..=4 {
+        total += x;
+    }
+    var double = func(y) { return y * 2; };
+    var message = "${greeting} ${double(total)} ${len(xs)}";
+}
+"#;
+
+#[test]
+fn ast_json_has_variant_names_and_fields() {
+    let json = json::ast_to_json(&parse("begin { var x = 1 + y; }"));
+    assert_eq!(
+        json,
+        r#"[{"EntryBlock":{"span":{"start":0,"end":5},"body":[{"VarDeclaration":{"variable":"x","#.to_string()
+            + r#""span":{"start":12,"end":13},"value":{"Add":{"lhs":{"IntLit":{"value":1}},"#
+            + r#""rhs":{"VarRef":{"variable":"y","span":{"start":20,"end":21}}}}},"is_const":false}}]}}]"#,
+    );
+}
+
+#[test]
+fn ast_round_trips_through_json() {
+    let json = json::ast_to_json(&parse(PROGRAM));
+    let loaded = json::ast_from_json(&json).unwrap();
+    assert_eq!(json::ast_to_json(&loaded), json);
+}
+
+#[test]
+fn loaded_program_runs_like_the_source() {
+    let json = json::ast_to_json(&parse(PROGRAM));
+    assert_eq!(foolang::run_json(&json).unwrap(), foolang::run_source(PROGRAM).unwrap());
+}
+
+#[test]
+fn float_too_big_for_f64_round_trips_as_infinity() {
+    let src = format!("begin {{ var big = 1{}.0; }}", "0".repeat(400));
+    let json = json::ast_to_json(&parse(&src));
+    assert!(json.contains(r#"{"FloatLit":{"value":null}}"#), "{json}");
+    assert_eq!(foolang::run_json(&json).unwrap()["big"], foolang::interp::Value::Float(f64::INFINITY));
+}
+
+#[test]
+fn malformed_json_is_an_error() {
+    assert!(json::ast_from_json("[{\"EntryBlock\":").is_err());
+    assert!(json::ast_from_json("[{\"NotAnItem\":{}}]").is_err());
+}
+
+#[test]
+fn long_operator_chain_loads_and_runs() {
+    let terms: Vec<String> = (0..600).map(|i| i.to_string()).collect();
+    let src = format!("begin {{ var sum = {}; }}", terms.join(" + "));
+    let json = json::ast_to_json(&parse(&src));
+    assert_eq!(foolang::run_json(&json).unwrap()["sum"], foolang::interp::Value::Int(599 * 600 / 2));
+}
+
+#[test]
+fn nesting_deeper_than_the_parser_allows_is_an_error() {
+    let depth = ast::DEFAULT_MAX_DEPTH;
+    let nested = "{\"Not\":{\"operand\":".repeat(depth) + "{\"BoolLit\":{\"value\":true}}" + &"}}".repeat(depth);
+    let program = |value: &str| format!(
+        r#"[{{"EntryBlock":{{"span":{{"start":0,"end":5}},"body":[{{"ExprStmt":{{"expr":{value}}}}}]}}}}]"#,
+    );
+    assert!(json::ast_from_json(&program("{\"BoolLit\":{\"value\":true}}")).is_ok());
+    let err = json::ast_from_json(&program(&nested)).unwrap_err();
+    assert!(err.to_string().contains("nested too deeply"), "{err}");
+}
+
+#[test]
+fn chains_longer_than_the_parser_allows_are_an_error() {
+    let one = "{\"IntLit\":{\"value\":1}}";
+    let chain = |length: usize| {
+        let mut json = one.to_string();
+        for _ in 0..length {
+            json = format!("{{\"Add\":{{\"lhs\":{json},\"rhs\":{one}}}}}");
+        }
+        format!(r#"[{{"EntryBlock":{{"span":{{"start":0,"end":5}},"body":[{{"ExprStmt":{{"expr":{json}}}}}]}}}}]"#)
+    };
+    assert!(json::ast_from_json(&chain(ast::MAX_CHAIN_LENGTH)).is_ok());
+    let err = json::ast_from_json(&chain(ast::MAX_CHAIN_LENGTH + 1)).unwrap_err();
+    assert!(err.to_string().contains("longer than the parser allows"), "{err}");
+}
+
+#[test]
+fn names_that_arent_identifiers_are_an_error() {
+    let json = json::ast_to_json(&parse("begin { var x = 1; }"));
+    for name in ["while", "", "a b", "1x"] {
+        let renamed = json.replace(r#""variable":"x""#, &format!(r#""variable":{name:?}"#));
+        let err = json::ast_from_json(&renamed).unwrap_err();
+        assert!(err.to_string().contains("isn't a valid name"), "{name}: {err}");
+    }
+}
+
+#[test]
+fn span_ending_before_it_starts_is_an_error() {
+    let json = json::ast_to_json(&parse("begin { }"));
+    assert!(json::ast_from_json(&json.replace(r#"{"start":0,"end":5}"#, r#"{"start":5,"end":0}"#)).is_err());
+}
+
+#[test]
+fn assigning_to_something_unassignable_is_an_error() {
+    let json = json::ast_to_json(&parse("begin { var x = 1; x = 2; }"));
+    let target = r#"{"VarRef":{"variable":"x","span":{"start":19,"end":20}}}"#;
+    assert!(json.contains(target), "{json}");
+    assert!(json::ast_from_json(&json.replace(target, r#"{"IntLit":{"value":1}}"#)).is_err());
+}
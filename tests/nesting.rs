@@ -0,0 +1,94 @@
+use foolang::ast::{ParseError, DEFAULT_MAX_DEPTH, MAX_CHAIN_LENGTH};
+use foolang::interp::{RuntimeError, MAX_CALL_DEPTH};
+use foolang::lex::{LexError, LexErrorKind};
+use foolang::{run_source, Error};
+
+/// The stack size that spawned threads get by default, which the depth limit is meant to fit in
+const SMALL_STACK: usize = 2 << 20;
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Ran,
+    TooDeep,
+    TooLong,
+    CallsTooDeep,
+}
+
+/// Run the program in `src` on a thread with a `SMALL_STACK`
+fn run_on_small_stack(src: String) -> Outcome {
+    run_on_stack(SMALL_STACK, src)
+}
+
+/// Run the program in `src` on a thread with `stack_size` bytes of stack
+fn run_on_stack(stack_size: usize, src: String) -> Outcome {
+    std::thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || match run_source(&src) {
+            Ok(_) => Outcome::Ran,
+            Err(Error::Parse(ParseError::NestingTooDeep(_))) => Outcome::TooDeep,
+            Err(Error::Lex(LexError { kind: LexErrorKind::NestingTooDeep, .. })) => Outcome::TooDeep,
+            Err(Error::Parse(ParseError::ChainTooLong(_))) => Outcome::TooLong,
+            Err(Error::Runtime(RuntimeError::StackOverflow)) => Outcome::CallsTooDeep,
+            Err(e) => panic!("{e}"),
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// A program whose begin block nests `inner` in `depth` of `open` and `close`
+fn nested(open: &str, inner: &str, close: &str, depth: usize) -> String {
+    format!("begin {{ var v = {}{inner}{}; }}", open.repeat(depth), close.repeat(depth))
+}
+
+#[test]
+fn deeply_nested_parens_are_an_error() {
+    assert_eq!(run_on_small_stack(nested("(", "1", ")", 100_000)), Outcome::TooDeep);
+}
+
+#[test]
+fn deeply_nested_interpolations_are_an_error() {
+    assert_eq!(run_on_small_stack(nested("\"${", "1", "}\"", 100_000)), Outcome::TooDeep);
+}
+
+#[test]
+fn long_postfix_chains_are_too_deep() {
+    assert_eq!(run_on_small_stack(nested("", "[[0]]", "[0]", 100_000)), Outcome::TooDeep);
+    assert_eq!(run_on_small_stack(nested("", "print", "()", 100_000)), Outcome::TooDeep);
+}
+
+#[test]
+fn nesting_up_to_the_limit_runs_on_a_small_stack() {
+    // the begin block and the innermost operand are levels too
+    let depth = DEFAULT_MAX_DEPTH - 2;
+    assert_eq!(run_on_small_stack(nested("{ ", "1", " }", depth)), Outcome::Ran);
+    assert_eq!(run_on_small_stack(nested("[", "", "]", depth)), Outcome::Ran);
+    assert_eq!(run_on_small_stack(nested("{ ", "1", " }", depth + 1)), Outcome::TooDeep);
+}
+
+#[test]
+fn recursion_up_to_the_call_depth_limit_runs_on_a_small_stack() {
+    let recurse = |depth: usize, nesting: usize| {
+        // the recursive call is as deeply nested in the body as the parser allows
+        let call = format!("{}f(n - 1){}", "-(-".repeat(nesting), ")".repeat(nesting));
+        format!("func f(n) {{ if n == 0 {{ return 0; }} return {call} + 1; }} begin {{ var v = f({depth}); }}")
+    };
+    assert_eq!(run_on_small_stack(recurse(MAX_CALL_DEPTH - 1, 0)), Outcome::Ran);
+    assert_eq!(run_on_small_stack(recurse(MAX_CALL_DEPTH - 1, 20)), Outcome::Ran);
+    assert_eq!(run_on_small_stack(recurse(MAX_CALL_DEPTH, 0)), Outcome::CallsTooDeep);
+}
+
+#[test]
+fn operator_chains_up_to_the_limit_run_on_a_small_stack() {
+    let chain = |op: &str, operand: &str, length: usize| {
+        format!("begin {{ var v = {}; }}", vec![operand; length + 1].join(&format!(" {op} ")))
+    };
+    for (op, operand) in [("+", "1"), ("-", "1"), ("*", "1"), ("/", "1"), ("%", "1"), ("&&", "true"), ("||", "false")] {
+        assert_eq!(run_on_small_stack(chain(op, operand, MAX_CHAIN_LENGTH)), Outcome::Ran, "a chain of {op}");
+        assert_eq!(run_on_small_stack(chain(op, operand, MAX_CHAIN_LENGTH + 1)), Outcome::TooLong, "a chain of {op}");
+    }
+    // a parenthesized chain on the left is part of the same chain
+    let half = vec!["1"; MAX_CHAIN_LENGTH / 2 + 1].join(" + ");
+    assert_eq!(run_on_small_stack(format!("begin {{ var v = ({half}) + {half}; }}")), Outcome::TooLong);
+    assert_eq!(run_on_small_stack(format!("begin {{ var v = {half} + ({half}); }}")), Outcome::Ran);
+}
@@ -0,0 +1,31 @@
+use foolang::ast::{self, Item};
+use foolang::interp::{Program, Value};
+use foolang::lex;
+
+fn parse_owned(src: &str) -> Vec<Item<'static>> {
+    let tokens = lex::lex_tokens(src).map(Result::unwrap);
+    ast::parse_items(tokens).map(|item| item.unwrap().to_owned()).collect()
+}
+
+#[test]
+fn owned_ast_runs_after_source_is_dropped() {
+    let src = String::from("func double(x) { return x * 2; }\nvar base = 20;\nbegin { var total = double(base) + 2; }");
+    let items = parse_owned(&src);
+    drop(src);
+
+    let program = Program::from_items(items.into_iter()).unwrap();
+    let vars = program.execute().unwrap().into_variables();
+    assert_eq!(vars["total"], Value::Int(42));
+}
+
+#[test]
+fn owned_idents_keep_their_names_and_symbols() {
+    let items = parse_owned("begin { var x = 1; var y = x; }");
+    let Item::EntryBlock { body, .. } = &items[0] else { panic!("expected the begin block") };
+    let ast::Statement::VarDeclaration { variable, .. } = &body[0] else { panic!("expected a declaration") };
+    assert_eq!(variable.name(), "x");
+    let ast::Statement::VarDeclaration { value: ast::Expr::VarRef { variable: read, .. }, .. } = &body[1] else {
+        panic!("expected a declaration initialized with a variable");
+    };
+    assert_eq!(variable, read);
+}
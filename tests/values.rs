@@ -0,0 +1,211 @@
+use foolang::check::SemanticError;
+use foolang::interp::{RuntimeError, Value};
+use foolang::{run_source, Error};
+
+mod common;
+use common::{eval, eval_err, run_begin};
+
+#[test]
+fn running_gives_the_begin_blocks_variables() {
+    let vars = run_begin("var x = 42; var b = true; var f = 1.5; var s = \"hi\";");
+    assert_eq!(vars.len(), 4);
+    assert_eq!(vars["x"], Value::Int(42));
+    assert_eq!(vars["b"], Value::Bool(true));
+    assert_eq!(vars["f"], Value::Float(1.5));
+    assert_eq!(vars["s"], Value::String("hi".into()));
+}
+
+#[test]
+fn string_escapes() {
+    assert_eq!(eval(r#""hi\n""#), Value::String("hi\n".into()));
+    assert_eq!(eval(r#""tab\tquote\"slash\\""#), Value::String("tab\tquote\"slash\\".into()));
+}
+
+#[test]
+fn string_concatenation() {
+    assert_eq!(eval(r#""foo" + "bar""#), Value::String("foobar".into()));
+    assert!(matches!(eval_err(r#""x" + 1"#), RuntimeError::TypeError(_)));
+    assert!(matches!(eval_err(r#"1 + "x""#), RuntimeError::TypeError(_)));
+}
+
+#[test]
+fn string_interpolation() {
+    assert_eq!(run_begin(r#"var n = 4; var s = "n is ${n}, and ${n * 2} doubled";"#)["s"], Value::String("n is 4, and 8 doubled".into()));
+    assert_eq!(eval(r#""cost: \$5""#), Value::String("cost: $5".into()));
+    assert_eq!(eval(r#""${[1, "a"]} ${"b"}""#), Value::String(r#"[1, "a"] b"#.into()));
+    assert_eq!(eval(r#""${ "${1 + 1}" }!""#), Value::String("2!".into()));
+}
+
+#[test]
+fn display_forms() {
+    for (expr, shown) in [
+        ("42", "42"),
+        ("-7", "-7"),
+        ("2.0", "2.0"),
+        ("0.5", "0.5"),
+        ("1.0 / 0.0", "inf"),
+        ("true", "true"),
+        (r#""a \"b\"""#, r#"a "b""#),
+        (r#"[1, 2.5, "s", [true]]"#, r#"[1, 2.5, "s", [true]]"#),
+        ("[]", "[]"),
+        (r#"#{"b": 1, "a": "x"}"#, r#"#{"a": "x", "b": 1}"#),
+        ("#{}", "#{}"),
+        ("print(1)", "()"),
+        ("len", "<function len>"),
+        ("func() { }", "<function <lambda>>"),
+    ] {
+        assert_eq!(eval(expr).to_string(), shown, "{expr}");
+    }
+}
+
+#[test]
+fn type_names() {
+    for (expr, ty) in [
+        ("1", "int"),
+        ("1.0", "float"),
+        ("false", "bool"),
+        (r#""s""#, "string"),
+        ("[1]", "array"),
+        (r#"#{"k": 1}"#, "map"),
+        ("print(1)", "unit"),
+        ("len", "function"),
+        ("func() { }", "function"),
+    ] {
+        assert_eq!(eval(&format!("type({expr})")), Value::String(ty.into()), "{expr}");
+    }
+}
+
+#[test]
+fn comparing_across_types() {
+    assert_eq!(eval("1 == 1.0"), Value::Bool(true));
+    assert_eq!(eval("1 < 1.5"), Value::Bool(true));
+    assert_eq!(eval(r#"1 == "1""#), Value::Bool(false));
+    assert_eq!(eval("true != 1"), Value::Bool(true));
+    assert_eq!(eval("[1, [2]] == [1.0, [2]]"), Value::Bool(true));
+    assert_eq!(eval(r#"#{"a": 1} == #{"a": 1.0}"#), Value::Bool(true));
+    assert_eq!(eval("0.0 / 0.0 == 0.0 / 0.0"), Value::Bool(false));
+    assert!(matches!(eval_err(r#""a" < "b""#), RuntimeError::TypeError(_)));
+    assert!(matches!(eval_err("true < 1"), RuntimeError::TypeError(_)));
+}
+
+#[test]
+fn unit_is_what_calls_without_a_return_give() {
+    assert_eq!(eval("print(1)"), Value::Unit);
+    let vars = run_source("func nothing(x) { var y = x; } begin { nothing(1); var u = nothing(2); }").unwrap();
+    assert_eq!(vars["u"], Value::Unit);
+}
+
+#[test]
+fn block_expression() {
+    let vars = run_begin("var x = { var t = 2; t * 3 }; var y = { };");
+    assert_eq!(vars["x"], Value::Int(6));
+    assert_eq!(vars["y"], Value::Unit);
+    assert!(!vars.contains_key("t"));
+}
+
+#[test]
+fn conditional_expression() {
+    let vars = run_begin("var a = 3; var b = 5; var min = if a < b then a else b; var max = if a > b then a else b;");
+    assert_eq!(vars["min"], Value::Int(3));
+    assert_eq!(vars["max"], Value::Int(5));
+    // only the branch that's picked is evaluated
+    assert_eq!(eval("if true then 1 else 1 / 0"), Value::Int(1));
+}
+
+#[test]
+fn conditional_expression_as_a_blocks_value() {
+    assert_eq!(eval("{ if true then 1 else 2 }"), Value::Int(1));
+    assert_eq!(eval("{ var t = 3; if t > 5 then \"big\" else \"small\" }"), Value::String("small".into()));
+    // an if with blocks is still a statement
+    assert_eq!(eval("{ var t = 0; if true { t = 4; } t * 2 }"), Value::Int(8));
+    assert_eq!(eval("{ if true { } else { } }"), Value::Unit);
+}
+
+#[test]
+fn arrays() {
+    let vars = run_begin("var a = [10, 20, 30]; var first = a[0]; var last = a[len(a) - 1]; var nested = [[1, 2], [3]][0][1];");
+    assert_eq!(vars["a"].to_string(), "[10, 20, 30]");
+    assert_eq!(vars["first"], Value::Int(10));
+    assert_eq!(vars["last"], Value::Int(30));
+    assert_eq!(vars["nested"], Value::Int(2));
+    assert!(matches!(eval_err("[10, 20, 30][3]"), RuntimeError::IndexOutOfBounds { index: 3, len: 3 }));
+    assert!(matches!(eval_err("[10][-1]"), RuntimeError::IndexOutOfBounds { index: -1, len: 1 }));
+    assert!(matches!(eval_err("[10][true]"), RuntimeError::TypeError(_)));
+}
+
+#[test]
+fn len_counts_elements_and_chars() {
+    assert_eq!(eval("len([1, 2, 3])"), Value::Int(3));
+    assert_eq!(eval("len([])"), Value::Int(0));
+    assert_eq!(eval(r#"len("abc")"#), Value::Int(3));
+    // chars, not bytes
+    assert_eq!(eval(r#"len("héllo")"#), Value::Int(5));
+    assert_eq!(eval(r##"len(#{"a": 1, "b": 2})"##), Value::Int(2));
+    assert!(matches!(eval_err("len(5)"), RuntimeError::TypeError(_)));
+}
+
+#[test]
+fn maps() {
+    let vars = run_begin(r#"var m = #{"one": 1, "two": 2}; var one = m["one"]; m["three"] = 3; m["one"] = 10;"#);
+    assert_eq!(vars["one"], Value::Int(1));
+    assert_eq!(vars["m"].to_string(), r#"#{"one": 10, "three": 3, "two": 2}"#);
+    assert!(matches!(eval_err(r#"#{"a": 1}["b"]"#), RuntimeError::MissingKey(key) if key == "b"));
+    assert!(matches!(eval_err(r#"#{"a": 1}[1]"#), RuntimeError::TypeError(_)));
+    assert!(matches!(eval_err("#{1: 1}"), RuntimeError::TypeError(_)));
+}
+
+#[test]
+fn reading_a_variable_shares_its_array() {
+    let vars = run_begin("var a = [1, 2, 3]; var b = a; var c = [a][0];");
+    let (Value::Array(a), Value::Array(b), Value::Array(c)) = (&vars["a"], &vars["b"], &vars["c"]) else {
+        panic!("expected arrays");
+    };
+    assert!(std::rc::Rc::ptr_eq(a, b));
+    assert!(std::rc::Rc::ptr_eq(a, c));
+}
+
+#[test]
+fn multiple_declarations() {
+    let vars = run_begin("var a = 1, b = a + 1; const c = 3, d = c * 2;");
+    assert_eq!(vars["b"], Value::Int(2));
+    assert_eq!(vars["d"], Value::Int(6));
+}
+
+#[test]
+fn stray_semicolons_are_empty_statements() {
+    let vars = run_begin("; var x = 1; ;;");
+    assert_eq!(vars["x"], Value::Int(1));
+}
+
+#[test]
+fn consts_can_be_read_but_not_assigned() {
+    let vars = run_begin("const c = 5; var d = c + 1;");
+    assert_eq!(vars["d"], Value::Int(6));
+    for src in ["begin { const c = 5; c = 6; }", "begin { const c = 5; c += 1; }", "const g = 1; begin { g = 2; }"] {
+        assert!(matches!(run_source(src), Err(Error::Runtime(RuntimeError::AssignToConst(name))) if name == "c" || name == "g"), "{src}");
+    }
+}
+
+#[test]
+fn locals_are_scoped_to_their_block() {
+    let vars = run_begin("var outer = 1; if true { var inner = 2; outer = inner; }");
+    assert_eq!(vars["outer"], Value::Int(2));
+    assert!(!vars.contains_key("inner"));
+
+    let result = run_source("begin { if true { var inner = 2; } var x = inner; }");
+    let Err(Error::Semantic(errors)) = result else {
+        panic!("reading inner outside its block should fail the check");
+    };
+    assert!(matches!(&errors[..], [SemanticError::UndefinedVariable { name, .. }] if name == "inner"));
+
+    // a variable in an inner block can shadow an outer one
+    let vars = run_begin("var x = 1; var seen = 0; if true { var x = 2; seen = x; }");
+    assert_eq!(vars["x"], Value::Int(1));
+    assert_eq!(vars["seen"], Value::Int(2));
+}
+
+#[test]
+fn identifiers_with_digits_and_underscores() {
+    let vars = run_begin("var my_var2 = 1; var _x = my_var2 + 1;");
+    assert_eq!(vars["_x"], Value::Int(2));
+}
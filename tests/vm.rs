@@ -0,0 +1,59 @@
+use foolang::compile::{self, CompileError};
+use foolang::interp::{RuntimeError, Value};
+use foolang::vm::Vm;
+use foolang::{run_source, Error};
+use std::collections::HashMap;
+
+mod common;
+use common::parse;
+
+/// Compile `src` and run it on the VM
+fn run_compiled(src: &str) -> Result<HashMap<String, Value>, RuntimeError> {
+    let bytecode = compile::compile(parse(src).into_iter()).unwrap_or_else(|e| panic!("{src} didn't compile: {e}"));
+    Vm::new(&bytecode).run()
+}
+
+#[test]
+fn compiled_programs_give_the_same_variables() {
+    for src in [
+        "begin { var x = 1 + 2 * 3; var y = (x - 1) / 2; var z = x % 4 ** 2; }",
+        "begin { var f = 1.5 * 2; var b = !(1 < 2) || 3 >= 3; var s = \"a\" + \"b\"; }",
+        "func add(a, b) { return a + b; } begin { var x = add(1, add(2, 3)); }",
+        "func fact(n) { if n <= 1 { return 1; } return n * fact(n - 1); } begin { var f = fact(10); }",
+        "func count(n, acc) { if n == 0 { return acc; } return count(n - 1, acc + 1); } begin { var c = count(100000, 0); }",
+        "var g = 2; const h = 3; func get() { g += h; return g; } begin { var a = get(); var b = get(); }",
+        "begin { var sum = 0; var i = 0; while i < 10 { i += 1; if i % 2 == 0 { continue; } sum += i; } }",
+        "begin { var sum = 0; for (var i = 0; i < 100; i += 1) { if i == 5 { break; } sum += i; } }",
+        "func nothing() { } begin { var u = nothing(); var t = type(1.0); var m = max(3, abs(-4)); }",
+        "begin { var x = 1; if true { var x = 2; } var y = if x == 1 then \"one\" else \"other\"; }",
+    ] {
+        let interpreted = run_source(src).unwrap_or_else(|e| panic!("{src} failed: {e}"));
+        let compiled = run_compiled(src).unwrap_or_else(|e| panic!("{src} failed on the VM: {e}"));
+        assert_eq!(compiled, interpreted, "{src}");
+    }
+}
+
+#[test]
+fn compiled_programs_give_the_same_errors() {
+    for src in [
+        "begin { var x = 1 / 0; }",
+        "begin { var x = 9223372036854775807 + 1; }",
+        "begin { const c = 1; c = 2; }",
+        "begin { if 1 { } }",
+        "func f() { return 1 + f(); } begin { f(); }",
+        "func f(x: int) { } begin { f(1.0); }",
+    ] {
+        let Err(Error::Runtime(interpreted)) = run_source(src) else {
+            panic!("{src} should fail at runtime");
+        };
+        let compiled = run_compiled(src).expect_err(src);
+        assert_eq!(compiled.to_string(), interpreted.to_string(), "{src}");
+    }
+}
+
+#[test]
+fn unsupported_features_are_a_compile_error() {
+    for src in ["begin { var a = [1]; }", "begin { var f = func() { }; }", "begin { for x in 0..3 { } }"] {
+        assert!(matches!(compile::compile(parse(src).into_iter()), Err(CompileError::Unsupported { .. })), "{src}");
+    }
+}